@@ -1,7 +1,9 @@
 (function() {
   'use strict';
   // Lightweight production bridge (phase3 cleanup)
-  const BRIDGE_VERSION = '0.2.1';
+  const BRIDGE_VERSION = '0.5.0';
+  // Bridge protocol versions this script speaks (see schema/*.schema.json)
+  const PROTOCOL_VERSIONS = [1, 2];
   const DEBUG = false; // flip to true for extra queue / send logs
   
   const WS_URL = 'ws://127.0.0.1:8123';
@@ -13,9 +15,18 @@
   let reconnectAttempts = 0;
   let reconnectDelay = INITIAL_RECONNECT_DELAY;
   let isInjected = false;
-  let messageQueue = [];
+  // seq -> NET_CAPTURE text, kept until the app ACKs it (v2) so nothing is lost across reconnects
+  const unacked = new Map();
+  let nextSeq = 1;
+  // Resent captures are recognised by (CLIENT_ID, seq) and not stored twice
+  const CLIENT_ID = crypto.randomUUID();
   let wsConnecting = false; // Thêm flag để tránh spam connection
+  let protocolVersion = null; // set by WELCOME; captures are queued until then
+  let incompatible = false; // app rejected our protocol -> stop reconnecting
+  let pairingToken = null; // from the popup (chrome.storage.local), sent in HELLO
+  let unauthorized = false; // app rejected the token -> wait for a new one
   const MAX_QUEUE_SIZE = 100;
+  const INGEST_RETRY_DELAY = 5000;
 
   function injectScript() {
     if (isInjected) return;
@@ -47,6 +58,11 @@
     if (wsConnecting) {
       return; // Already in connecting process
     }
+
+    if (!pairingToken) {
+      console.warn('[P2P Ext] Not paired: paste the pairing token from the app into the extension popup');
+      return;
+    }
     
     wsConnecting = true;
     
@@ -58,17 +74,52 @@
         wsConnecting = false;
         reconnectAttempts = 0;
         reconnectDelay = INITIAL_RECONNECT_DELAY;
-        
-        // Send queued messages
-        while (messageQueue.length > 0 && ws.readyState === WebSocket.OPEN) {
-          const message = messageQueue.shift();
-          ws.send(message);
+        ws.send(JSON.stringify({
+          kind: 'HELLO',
+          protocol_versions: PROTOCOL_VERSIONS,
+          extension_version: BRIDGE_VERSION,
+          client_id: CLIENT_ID,
+          token: pairingToken
+        }));
+      };
+
+      ws.onmessage = (event) => {
+        let frame;
+        try { frame = JSON.parse(event.data); } catch (e) { return; }
+        if (frame.kind === 'WELCOME') {
+          protocolVersion = frame.protocol_version;
+          console.log(`[P2P Ext] Protocol v${protocolVersion} (app ${frame.app_version})`);
+          // Everything not yet acknowledged, oldest first (Map keeps insertion order)
+          for (const seq of Array.from(unacked.keys())) {
+            if (!trySend(seq)) break;
+          }
+        } else if (frame.kind === 'ACK') {
+          unacked.delete(frame.seq);
+        } else if (frame.kind === 'COMMAND') {
+          handleCommand(frame);
+        } else if (frame.kind === 'ERROR') {
+          console.error(`[P2P Ext] App rejected frame (${frame.code}): ${frame.message}`);
+          if (frame.code === 'UNSUPPORTED_VERSION' || frame.code === 'HELLO_REQUIRED') incompatible = true;
+          if (frame.code === 'UNAUTHORIZED') unauthorized = true;
+          // App could not store it (DB busy...): still unacked, try again shortly
+          if (frame.code === 'INGEST_FAILED' && frame.seq != null) {
+            setTimeout(() => trySend(frame.seq), INGEST_RETRY_DELAY);
+          }
         }
       };
       
       ws.onclose = (event) => {
         console.log(`[P2P Ext] WebSocket closed (code: ${event.code}, reason: ${event.reason})`);
         wsConnecting = false;
+        protocolVersion = null;
+        if (incompatible) {
+          console.error('[P2P Ext] Extension and app protocol versions are incompatible - update one of them. Not reconnecting.');
+          return;
+        }
+        if (unauthorized) {
+          console.error('[P2P Ext] Pairing token rejected - paste the current token from the app into the extension popup.');
+          return;
+        }
         scheduleReconnect();
       };
       
@@ -101,33 +152,73 @@
     reconnectDelay = Math.min(reconnectDelay * 2, MAX_RECONNECT_DELAY);
   }
 
+  function isReady() {
+    return ws && ws.readyState === WebSocket.OPEN && protocolVersion !== null;
+  }
+
+  // Send one pending capture; v1 apps never ACK so it is dropped once sent
+  function trySend(seq) {
+    const message = unacked.get(seq);
+    if (message === undefined || !isReady()) return false;
+    try {
+      ws.send(message);
+    } catch (e) {
+      if (DEBUG) console.error('[P2P Ext] Send failed, keeping', seq, e);
+      return false;
+    }
+    if (protocolVersion < 2) unacked.delete(seq);
+    return true;
+  }
+
   function sendMessage(data) {
     const payload = data.__P2P_CAPTURE__;
     if (!payload) {
       if (DEBUG) console.warn('[P2P Ext] Missing __P2P_CAPTURE__ on data');
       return;
     }
-    const messageObj = {
+    const seq = nextSeq++;
+    unacked.set(seq, JSON.stringify({
       kind: 'NET_CAPTURE',
+      seq,
       payload,
       extension_version: BRIDGE_VERSION,
       page_url: window.location.href,
       ts_send: Date.now()
-    };
-    const message = JSON.stringify(messageObj);
+    }));
+    if (unacked.size > MAX_QUEUE_SIZE) unacked.delete(unacked.keys().next().value);
+
+    if (trySend(seq)) {
+      if (DEBUG) console.log('[P2P Ext] Sent ->', seq, payload.url);
+    } else if (DEBUG) {
+      console.debug('[P2P Ext] Queue size', unacked.size, 'waiting WS for', payload.url);
+    }
+  }
 
-    if (ws && ws.readyState === WebSocket.OPEN) {
-      try {
-        ws.send(message);
-        if (DEBUG) console.log('[P2P Ext] Sent ->', payload.url);
-      } catch (e) {
-        if (DEBUG) console.error('[P2P Ext] Send failed, queueing', e);
-        messageQueue.push(message);
-      }
-    } else {
-      messageQueue.push(message);
-      if (messageQueue.length > MAX_QUEUE_SIZE) messageQueue.shift();
-      if (DEBUG) console.debug('[P2P Ext] Queue size', messageQueue.length, 'waiting WS for', payload.url);
+  // App -> extension command (protocol v2); always answered with COMMAND_RESULT
+  function handleCommand(frame) {
+    const { id, command } = frame;
+    const reply = (ok, result, error) => {
+      if (!isReady()) return;
+      ws.send(JSON.stringify({ kind: 'COMMAND_RESULT', id, ok, result: result ?? null, error: error ?? null }));
+    };
+    switch (command && command.type) {
+      case 'REFETCH_ORDER_DETAIL':
+        if (!isInjected) return reply(false, null, 'Page hook not loaded');
+        // injected.js repeats the request in the page; the response comes back as a normal capture
+        window.postMessage({ __P2P_COMMAND__: { type: 'REFETCH_ORDER_DETAIL', orderNumber: command.order_number } }, '*');
+        return reply(true, { requested: command.order_number });
+      case 'REPORT_STATUS':
+        return reply(true, {
+          extension_version: BRIDGE_VERSION,
+          protocol_version: protocolVersion,
+          client_id: CLIENT_ID,
+          injected: isInjected,
+          unacked: unacked.size,
+          next_seq: nextSeq,
+          page_url: window.location.href
+        });
+      default:
+        return reply(false, null, `Unknown command ${command && command.type}`);
     }
   }
 
@@ -155,11 +246,25 @@
     }
   });
 
+  // New token saved in the popup: reconnect with it
+  chrome.storage.onChanged.addListener((changes, area) => {
+    if (area !== 'local' || !changes.pairingToken) return;
+    pairingToken = changes.pairingToken.newValue || null;
+    unauthorized = false;
+    reconnectAttempts = 0;
+    reconnectDelay = INITIAL_RECONNECT_DELAY;
+    if (ws && ws.readyState === WebSocket.OPEN) ws.close(); // onclose reconnects
+    else connectWS();
+  });
+
   // Initialize
   function initialize() {
     if (DEBUG) console.log('[P2P Ext] Initializing bridge', BRIDGE_VERSION, 'state=', document.readyState);
     injectScript();
-    connectWS();
+    chrome.storage.local.get('pairingToken', (items) => {
+      pairingToken = items.pairingToken || null;
+      connectWS();
+    });
   }
 
   // Run immediately (document_start) to avoid missing early requests
@@ -1,6 +1,6 @@
 (function() {
   'use strict';
-  const VERSION = '0.2.1';
+  const VERSION = '0.3.0';
   const DEBUG = false; // set true for limited console diagnostics
   
   // Enhanced target patterns with more specific matching
@@ -13,9 +13,16 @@
 
   const RELEVANT_KEYWORDS = [
     'order', 'pay', 'match', 'trade', 'detail', 
-    'getOrderDetail', 'getPayMethod', 'orderStatus'
+    'getOrderDetail', 'getPayMethod', 'orderStatus',
+    // logged-in user's profile -> "my nickname" on the desktop side
+    'base-detail', 'user-info'
   ];
 
+  // Order detail endpoint the page itself calls; used for REFETCH_ORDER_DETAIL
+  const ORDER_DETAIL_URL = '/bapi/c2c/v2/private/c2c/order-match/getUserOrderDetail';
+  // Headers of the page's last private c2c request (csrftoken, clienttype...) to replay them
+  let privateHeaders = {};
+
   // Debounce để tránh spam
   const sentUrls = new Set();
   const DEBOUNCE_TIME = 1000;
@@ -42,6 +49,15 @@
     return true;
   }
 
+  function rememberHeaders(url, headers) {
+    if (typeof url !== 'string' || !url.includes('/private/') || !headers) return;
+    const collected = {};
+    new Headers(headers).forEach((value, name) => {
+      if (name !== 'content-length') collected[name] = value;
+    });
+    if (Object.keys(collected).length) privateHeaders = collected;
+  }
+
   let diagCount = 0; // only used when DEBUG
   function emit(obj) {
     try {
@@ -68,13 +84,14 @@
   window.fetch = async function(...args) {
     const startTime = Date.now();
     
+    let url = args[0];
+    if (url && typeof url === 'object' && 'url' in url) {
+      url = url.url;
+    }
     try {
+      if (isTarget(url)) rememberHeaders(url, args[1]?.headers || args[0]?.headers);
       const response = await originalFetch.apply(this, args);
       
-      let url = args[0];
-      if (url && typeof url === 'object' && 'url' in url) {
-        url = url.url;
-      }
       
       if (isTarget(url)) {
         const clone = response.clone();
@@ -108,7 +125,20 @@
     let requestUrl = '';
     let requestMethod = 'GET';
     let startTime = 0;
+    const requestHeaders = {};
     
+    const setHeaderOriginal = xhr.setRequestHeader;
+    xhr.setRequestHeader = function(name, value) {
+      requestHeaders[name] = value;
+      return setHeaderOriginal.call(xhr, name, value);
+    };
+
+    const sendOriginal = xhr.send;
+    xhr.send = function(...args) {
+      if (isTarget(requestUrl)) rememberHeaders(requestUrl, requestHeaders);
+      return sendOriginal.apply(xhr, args);
+    };
+
     const openOriginal = xhr.open;
     xhr.open = function(method, url, ...rest) {
       requestUrl = url;
@@ -153,6 +183,21 @@
   
   window.XMLHttpRequest = PatchedXHR;
 
+  // Commands relayed by content.js; the request goes through the hooked fetch so its response is captured
+  window.addEventListener('message', (event) => {
+    if (event.source !== window) return;
+    const cmd = event.data && event.data.__P2P_COMMAND__;
+    if (!cmd) return;
+    if (cmd.type === 'REFETCH_ORDER_DETAIL' && cmd.orderNumber) {
+      window.fetch(ORDER_DETAIL_URL, {
+        method: 'POST',
+        credentials: 'include',
+        headers: { ...privateHeaders, 'content-type': 'application/json' },
+        body: JSON.stringify({ orderNumber: String(cmd.orderNumber) })
+      }).catch(error => console.warn('[P2P Inject] Refetch failed:', error));
+    }
+  });
+
   if (DEBUG) console.log('[P2P Inject] Hook installed -', VERSION);
 
   // Optional passive logging (disabled in prod) could be re-added here if needed.
@@ -31,6 +31,7 @@ async function testConnection() {
   testBtn.textContent = 'Testing...';
   
   try {
+    const { pairingToken } = await chrome.storage.local.get('pairingToken');
     const ws = new WebSocket('ws://127.0.0.1:8123');
     
     const timeout = setTimeout(() => {
@@ -40,19 +41,36 @@ async function testConnection() {
     }, 3000);
     
     ws.onopen = () => {
-      clearTimeout(timeout);
       ws.send(JSON.stringify({
-        kind: 'TEST',
-        payload: { test: true, timestamp: Date.now() }
+        kind: 'HELLO',
+        protocol_versions: [1, 2],
+        extension_version: chrome.runtime.getManifest().version,
+        token: pairingToken || null
       }));
-      resultDiv.textContent = '✅ Connected to Tauri app';
-      resultDiv.className = 'result success';
+    };
+
+    ws.onmessage = (event) => {
+      const frame = JSON.parse(event.data);
+      if (frame.kind !== 'WELCOME' && frame.kind !== 'ERROR') return;
+      clearTimeout(timeout);
+      if (frame.kind === 'WELCOME') {
+        ws.send(JSON.stringify({
+          kind: 'TEST',
+          payload: { test: true, timestamp: Date.now() }
+        }));
+        resultDiv.textContent = `✅ Connected to Tauri app (protocol v${frame.protocol_version})`;
+        resultDiv.className = 'result success';
+      } else {
+        resultDiv.textContent = `❌ ${frame.code}: ${frame.message}`;
+        resultDiv.className = 'result error';
+      }
       ws.close();
     };
     
     ws.onerror = () => {
       clearTimeout(timeout);
-      resultDiv.textContent = '❌ Cannot connect to Tauri app';
+      // 403 on the handshake looks the same: origin not allowed
+      resultDiv.textContent = `❌ Cannot connect to Tauri app (is it running? is ${location.origin} in ws.allowed_origins?)`;
       resultDiv.className = 'result error';
     };
     
@@ -65,4 +83,21 @@ async function testConnection() {
   }
 }
 
+// Pairing token: content scripts pick it up via chrome.storage.onChanged
+async function saveToken() {
+  const input = document.getElementById('token-input');
+  const resultDiv = document.getElementById('test-result');
+  const token = input.value.trim();
+  await chrome.storage.local.set({ pairingToken: token || null });
+  resultDiv.textContent = token ? '✅ Token saved' : 'Token cleared';
+  resultDiv.className = token ? 'result success' : 'result error';
+}
+
+chrome.storage.local.get('pairingToken').then(({ pairingToken }) => {
+  const input = document.getElementById('token-input');
+  if (input && pairingToken) input.value = pairingToken;
+});
+
+document.getElementById('ext-origin').textContent = location.origin;
+document.getElementById('save-token-btn')?.addEventListener('click', saveToken);
 document.getElementById('test-btn')?.addEventListener('click', testConnection);
\ No newline at end of file
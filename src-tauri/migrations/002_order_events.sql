@@ -0,0 +1,12 @@
+-- Order stage transitions (timeline / dispute evidence)
+CREATE TABLE IF NOT EXISTS order_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  order_number TEXT NOT NULL,
+  from_code INTEGER,
+  to_code INTEGER NOT NULL,
+  source TEXT NOT NULL,
+  observed_at INTEGER NOT NULL,
+  FOREIGN KEY(order_number) REFERENCES orders(order_number) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_order_events_order ON order_events(order_number, observed_at);
@@ -0,0 +1,11 @@
+-- Master password vault (single row): Argon2id salt/params + verifier ciphertext
+CREATE TABLE IF NOT EXISTS vault_meta (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  kdf TEXT NOT NULL,
+  salt BLOB NOT NULL,
+  m_cost INTEGER NOT NULL,
+  t_cost INTEGER NOT NULL,
+  p_cost INTEGER NOT NULL,
+  verifier BLOB NOT NULL,
+  created_at INTEGER NOT NULL
+);
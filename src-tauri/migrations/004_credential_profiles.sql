@@ -0,0 +1,7 @@
+-- Active API credential profile (single row), NULL = fall back to the newest profile
+CREATE TABLE IF NOT EXISTS active_profile (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  credential_id INTEGER REFERENCES api_credentials(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_credentials_label ON api_credentials(label);
@@ -0,0 +1,10 @@
+-- Merchant account dimension: account_id = api_credentials.id of the account's profile, NULL = unassigned
+ALTER TABLE orders ADD COLUMN account_id INTEGER;
+CREATE INDEX IF NOT EXISTS idx_orders_account ON orders(account_id, create_time_ms);
+
+-- Binance nickname the extension saw logged in -> account
+CREATE TABLE IF NOT EXISTS account_nicknames (
+  nickname TEXT PRIMARY KEY,
+  account_id INTEGER NOT NULL,
+  updated_at INTEGER
+);
@@ -0,0 +1,2 @@
+-- How the nickname -> account link was made: manual | profile | pattern
+ALTER TABLE account_nicknames ADD COLUMN source TEXT NOT NULL DEFAULT 'manual';
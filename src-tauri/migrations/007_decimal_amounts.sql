@@ -0,0 +1,5 @@
+-- price / amount_asset / total_fiat are exact decimal strings (orders::decimal::Decimal).
+-- Missing values used to be stored as '' - make them NULL so they decode as None.
+UPDATE orders SET price = NULLIF(TRIM(price), '') WHERE price IS NOT NULL;
+UPDATE orders SET amount_asset = NULLIF(TRIM(amount_asset), '') WHERE amount_asset IS NOT NULL;
+UPDATE orders SET total_fiat = NULLIF(TRIM(total_fiat), '') WHERE total_fiat IS NOT NULL;
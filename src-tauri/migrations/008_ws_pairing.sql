@@ -0,0 +1,6 @@
+-- Pairing token the extension must present in HELLO (single row, rotated from the UI)
+CREATE TABLE IF NOT EXISTS ws_pairing (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  token TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
@@ -1,37 +1,216 @@
-use anyhow::{Result, anyhow};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use anyhow::Result;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
-use reqwest::Client;
+use reqwest::{Client, Response};
 use chrono::Utc;
 use serde_json::Value;
 
+use crate::api::error::ApiError;
+use crate::api::rate_limit::TokenBucket;
+
 type HmacSha256 = Hmac<Sha256>;
+/// Awaited after every successful signed request (e.g. to bump `last_used_at`).
+/// Runs inline so it never races the caller's own DB writes.
+pub type UsageHook = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+const MAX_RETRIES: u32 = 5;
+const BACKOFF_BASE_MS: u64 = 500;
+const BACKOFF_MAX_MS: u64 = 30_000;
+/// Longer Retry-After than this is surfaced to the caller instead of waited out
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+/// SAPI IP weight limit per minute; pause when the server reports usage above 90%
+const SAPI_IP_WEIGHT_LIMIT_1M: u32 = 12_000;
+const HISTORY_WEIGHT: u32 = 1;
+/// Re-measure the server clock offset at most this often
+const TIME_SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Binance rejects recvWindow above 60s
+const MAX_RECV_WINDOW_MS: u64 = 60_000;
+
+/// Client-side request budget (burst 20, 600/min). SAPI weight is counted per IP,
+/// so clients of different accounts should share one via `with_limiter`
+pub fn default_limiter() -> Arc<TokenBucket> {
+    Arc::new(TokenBucket::new(20, 600))
+}
 
 #[derive(Clone)]
 pub struct C2CApiClient {
     api_key: String,
     api_secret: String,
     http: Client,
-    base: String
+    base: String,
+    // Shared by every clone so background and manual syncs draw from one budget
+    limiter: Arc<TokenBucket>,
+    recv_window_ms: Option<u64>,
+    // server time - local time (ms), shared by every clone
+    time_offset_ms: Arc<AtomicI64>,
+    last_time_sync: Arc<Mutex<Option<Instant>>>,
+    on_used: Option<UsageHook>
 }
 
 impl C2CApiClient {
     pub fn new(api_key: String, api_secret: String) -> Self {
-        Self { api_key, api_secret, http: Client::new(), base: "https://api.binance.com".into() }
+        let http = Client::builder()
+            .timeout(Duration::from_secs(15))
+            .build()
+            .unwrap_or_default();
+        Self {
+            api_key, api_secret, http,
+            base: "https://api.binance.com".into(),
+            limiter: default_limiter(),
+            recv_window_ms: None,
+            time_offset_ms: Arc::new(AtomicI64::new(0)),
+            last_time_sync: Arc::new(Mutex::new(None)),
+            on_used: None
+        }
+    }
+
+    /// Point the client at another host (e.g. the `mock_binance` dev server)
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        self.base = base.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_recv_window(mut self, ms: u64) -> Self {
+        self.recv_window_ms = Some(ms.clamp(1, MAX_RECV_WINDOW_MS));
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: Arc<TokenBucket>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    pub fn with_usage_hook(mut self, hook: UsageHook) -> Self {
+        self.on_used = Some(hook);
+        self
+    }
+
+    pub fn time_offset_ms(&self) -> i64 {
+        self.time_offset_ms.load(Ordering::Relaxed)
+    }
+
+    /// Measure the offset against `/api/v3/time`, assuming symmetric latency
+    pub async fn sync_server_time(&self) -> Result<i64, ApiError> {
+        let before = Utc::now().timestamp_millis();
+        let res = self.http.get(format!("{}/api/v3/time", self.base)).send().await
+            .map_err(|e| ApiError::Transport(e.to_string()))?;
+        let text = res.text().await.map_err(|e| ApiError::Transport(e.to_string()))?;
+        let after = Utc::now().timestamp_millis();
+        let server_time = serde_json::from_str::<Value>(&text).ok()
+            .and_then(|v| v.get("serverTime").and_then(|x| x.as_i64()))
+            .ok_or_else(|| ApiError::Decode(format!("serverTime missing body={text}")))?;
+        let offset = server_time - (before + after) / 2;
+        self.time_offset_ms.store(offset, Ordering::Relaxed);
+        *self.last_time_sync.lock().await = Some(Instant::now());
+        println!("[API] Server time offset {offset}ms (rtt {}ms)", after - before);
+        Ok(offset)
+    }
+
+    async fn ensure_time_synced(&self) {
+        let stale = self.last_time_sync.lock().await.is_none_or(|t| t.elapsed() > TIME_SYNC_INTERVAL);
+        if stale {
+            if let Err(e) = self.sync_server_time().await {
+                // Keep signing with the previous offset
+                eprintln!("[API] Server time sync failed: {e}");
+                *self.last_time_sync.lock().await = Some(Instant::now());
+            }
+        }
     }
 
     pub async fn list_user_order_history(&self, trade_type: &str, start_ts: i64, end_ts: i64, page: u32, rows: u32) -> Result<Value> {
-        let timestamp = Utc::now().timestamp_millis();
-        let query = format!(
-            "tradeType={}&startTimestamp={}&endTimestamp={}&page={}&rows={}&timestamp={}",
-            trade_type, start_ts, end_ts, page, rows, timestamp
+        let params = format!(
+            "tradeType={}&startTimestamp={}&endTimestamp={}&page={}&rows={}",
+            trade_type, start_ts, end_ts, page, rows
         );
-        let signature = self.sign(&query)?;
-        let url = format!("{}/sapi/v1/c2c/orderMatch/listUserOrderHistory?{}&signature={}", self.base, query, signature);
-        let res = self.http.get(&url).header("X-MBX-APIKEY", &self.api_key).send().await?;
-        let text = res.text().await?;
-        let json: Value = serde_json::from_str(&text).map_err(|e| anyhow!("JSON parse error: {e} body={text}"))?;
-        if json.get("code").and_then(|x| x.as_str()) == Some("000000") { Ok(json) } else { Err(anyhow!("API error: {text}")) }
+        let json = self.signed_get("/sapi/v1/c2c/orderMatch/listUserOrderHistory", &params, HISTORY_WEIGHT).await?;
+        Ok(json)
+    }
+
+    /// Signed GET with client-side rate limiting and retry/backoff on transient failures
+    async fn signed_get(&self, path: &str, params: &str, weight: u32) -> Result<Value, ApiError> {
+        let mut attempt = 0;
+        let mut resynced = false;
+        loop {
+            self.ensure_time_synced().await;
+            self.limiter.acquire(weight).await;
+            let err = match self.signed_get_once(path, params).await {
+                Ok(v) => {
+                    if let Some(hook) = &self.on_used { hook().await; }
+                    return Ok(v);
+                }
+                Err(e) => e
+            };
+            if matches!(err, ApiError::TimestampOutOfWindow(_)) && !resynced {
+                // Clock drifted since the last measurement: re-measure once and retry
+                resynced = true;
+                if self.sync_server_time().await.is_ok() { continue; }
+            }
+            attempt += 1;
+            let retry_after = err.retry_after();
+            if !err.is_transient() || attempt > MAX_RETRIES || retry_after.is_some_and(|d| d > MAX_RETRY_AFTER) {
+                return Err(err);
+            }
+            let backoff = Duration::from_millis((BACKOFF_BASE_MS << (attempt - 1)).min(BACKOFF_MAX_MS));
+            let wait = retry_after.unwrap_or(backoff);
+            if retry_after.is_some() { self.limiter.pause_for(wait).await; }
+            eprintln!("[API] {err}; retry {attempt}/{MAX_RETRIES} in {}ms", wait.as_millis());
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    async fn signed_get_once(&self, path: &str, params: &str) -> Result<Value, ApiError> {
+        let timestamp = Utc::now().timestamp_millis() + self.time_offset_ms();
+        let query = match self.recv_window_ms {
+            Some(w) => format!("{params}&recvWindow={w}&timestamp={timestamp}"),
+            None => format!("{params}&timestamp={timestamp}")
+        };
+        let signature = self.sign(&query).map_err(|e| ApiError::Auth(e.to_string()))?;
+        let url = format!("{}{}?{}&signature={}", self.base, path, query, signature);
+        let res = self.http.get(&url).header("X-MBX-APIKEY", &self.api_key).send().await
+            .map_err(|e| ApiError::Transport(e.to_string()))?;
+
+        self.observe_weight(&res).await;
+        let status = res.status().as_u16();
+        let retry_after = retry_after(&res);
+        let text = res.text().await.map_err(|e| ApiError::Transport(e.to_string()))?;
+        let json: Value = match serde_json::from_str(&text) {
+            Ok(v) => v,
+            Err(e) => return Err(match status {
+                401 | 418 | 429 => ApiError::from_body(status, "", &text, retry_after),
+                s if s >= 400 => ApiError::Http { status, body: text },
+                _ => ApiError::Decode(format!("{e} body={text}"))
+            })
+        };
+        let code = match json.get("code") {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Number(n)) => n.to_string(),
+            _ => String::new()
+        };
+        if status < 400 && code == "000000" { return Ok(json); }
+        let msg = json.get("msg").or_else(|| json.get("message")).and_then(|x| x.as_str()).unwrap_or(&text);
+        Err(ApiError::from_body(status, &code, msg, retry_after))
+    }
+
+    /// Read Binance used-weight headers and back off before the server does
+    async fn observe_weight(&self, res: &Response) {
+        let used = ["x-sapi-used-ip-weight-1m", "x-mbx-used-weight-1m"].iter()
+            .filter_map(|h| res.headers().get(*h))
+            .filter_map(|v| v.to_str().ok()?.parse::<u32>().ok())
+            .max();
+        if let Some(used) = used {
+            if used.saturating_mul(10) >= SAPI_IP_WEIGHT_LIMIT_1M * 9 {
+                let into_minute = (Utc::now().timestamp_millis() % 60_000) as u64;
+                let wait = Duration::from_millis(60_000 - into_minute);
+                eprintln!("[API] Used weight {used}/{SAPI_IP_WEIGHT_LIMIT_1M}, pausing {}ms", wait.as_millis());
+                self.limiter.pause_for(wait).await;
+            }
+        }
     }
 
     fn sign(&self, query: &str) -> Result<String> {
@@ -40,3 +219,10 @@ impl C2CApiClient {
         Ok(hex::encode(mac.finalize().into_bytes()))
     }
 }
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    res.headers().get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
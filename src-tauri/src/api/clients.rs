@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::api::c2c_api_client::{default_limiter, C2CApiClient, UsageHook};
+use crate::api::credentials::{Credentials, CredentialsRepo};
+use crate::api::rate_limit::TokenBucket;
+
+/// One `C2CApiClient` per credential profile (= merchant account). Clients are
+/// cached so each account keeps its clock offset across syncs.
+pub struct ClientPool {
+    creds_repo: Arc<CredentialsRepo>,
+    build: fn(String, String) -> C2CApiClient,
+    // Binance weight limits are per IP: every account draws from this one bucket
+    limiter: Arc<TokenBucket>,
+    cache: Mutex<HashMap<i64, C2CApiClient>>
+}
+
+impl ClientPool {
+    pub fn new(creds_repo: Arc<CredentialsRepo>, build: fn(String, String) -> C2CApiClient) -> Self {
+        Self { creds_repo, build, limiter: default_limiter(), cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// (account id, client) of the active profile; errors while the vault is locked
+    pub async fn active(&self) -> Result<Option<(i64, C2CApiClient)>> {
+        Ok(self.creds_repo.active().await?.map(|c| (c.profile_id, self.client_for(c))))
+    }
+
+    pub async fn get(&self, account_id: i64) -> Result<Option<C2CApiClient>> {
+        Ok(self.creds_repo.load(account_id).await?.map(|c| self.client_for(c)))
+    }
+
+    /// Every profile, for syncing all accounts
+    pub async fn all(&self) -> Result<Vec<(i64, C2CApiClient)>> {
+        let mut out = Vec::new();
+        for p in self.creds_repo.list().await? {
+            if let Some(client) = self.get(p.id).await? { out.push((p.id, client)); }
+        }
+        Ok(out)
+    }
+
+    /// Drop cached clients (credentials changed, profile deleted or vault locked)
+    pub fn forget(&self, account_id: Option<i64>) {
+        let mut cache = self.cache.lock().unwrap();
+        match account_id {
+            Some(id) => { cache.remove(&id); }
+            None => cache.clear()
+        }
+    }
+
+    fn client_for(&self, creds: Credentials) -> C2CApiClient {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(creds.profile_id).or_insert_with(|| {
+            let repo = self.creds_repo.clone();
+            let id = creds.profile_id;
+            let touch: UsageHook = Arc::new(move || {
+                let repo = repo.clone();
+                Box::pin(async move {
+                    if let Err(e) = repo.touch(id, Utc::now().timestamp_millis()).await {
+                        eprintln!("[API] Failed to update last_used_at of profile {id}: {e}");
+                    }
+                })
+            });
+            println!("[API] Client ready for profile '{}'", creds.label);
+            (self.build)(creds.api_key, creds.api_secret)
+                .with_limiter(self.limiter.clone())
+                .with_usage_hook(touch)
+        }).clone()
+    }
+}
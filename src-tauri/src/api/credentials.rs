@@ -1,8 +1,27 @@
-use anyhow::Result;
-use sqlx::SqlitePool;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use sqlx::{SqlitePool, Row};
 use chrono::Utc;
 use crate::crypto::CryptoCtx;
 
+/// One named set of Binance API credentials (secrets never leave the repo)
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialProfile {
+    pub id: i64,
+    pub label: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub active: bool
+}
+
+/// Decrypted credentials of one profile
+pub struct Credentials {
+    pub profile_id: i64,
+    pub label: String,
+    pub api_key: String,
+    pub api_secret: String
+}
+
 pub struct CredentialsRepo {
     pool: SqlitePool,
     crypto: CryptoCtx
@@ -11,27 +30,131 @@ pub struct CredentialsRepo {
 impl CredentialsRepo {
     pub fn new(pool: SqlitePool, crypto: CryptoCtx) -> Self { Self { pool, crypto } }
 
-    pub async fn store(&self, label: &str, api_key: &str, api_secret: &str) -> Result<()> {
+    /// Save credentials under `label`; an existing profile with that label gets the new key.
+    /// Returns the profile id.
+    pub async fn store(&self, label: &str, api_key: &str, api_secret: &str) -> Result<i64> {
+        let label = check_label(label)?;
         let now = Utc::now().timestamp_millis();
         let api_key_enc = self.crypto.encrypt(api_key.as_bytes())?;
         let api_secret_enc = self.crypto.encrypt(api_secret.as_bytes())?;
-        sqlx::query(r#"INSERT INTO api_credentials(label, api_key_enc, api_secret_enc, created_at, last_used_at) VALUES (?1, ?2, ?3, ?4, ?4)"#)
+        if let Some(id) = self.id_of(label).await? {
+            sqlx::query("UPDATE api_credentials SET api_key_enc = ?2, api_secret_enc = ?3 WHERE id = ?1")
+                .bind(id).bind(api_key_enc).bind(api_secret_enc)
+                .execute(&self.pool).await?;
+            return Ok(id);
+        }
+        let res = sqlx::query(r#"INSERT INTO api_credentials(label, api_key_enc, api_secret_enc, created_at, last_used_at) VALUES (?1, ?2, ?3, ?4, NULL)"#)
             .bind(label)
             .bind(api_key_enc)
             .bind(api_secret_enc)
             .bind(now)
             .execute(&self.pool).await?;
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn list(&self) -> Result<Vec<CredentialProfile>> {
+        let active = self.active_id().await?;
+        let rows = sqlx::query("SELECT id, label, created_at, last_used_at FROM api_credentials ORDER BY id")
+            .fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(|r| {
+            let id: i64 = r.get("id");
+            CredentialProfile {
+                id,
+                label: r.get::<Option<String>,_>("label").unwrap_or_default(),
+                created_at: r.get::<Option<i64>,_>("created_at").unwrap_or(0),
+                last_used_at: r.get("last_used_at"),
+                active: active == Some(id)
+            }
+        }).collect())
+    }
+
+    /// Selected profile, or the newest one if none was ever selected
+    pub async fn active_id(&self) -> Result<Option<i64>> {
+        let selected: Option<i64> = sqlx::query_scalar("SELECT credential_id FROM active_profile WHERE id = 1")
+            .fetch_optional(&self.pool).await?.flatten();
+        match selected {
+            Some(id) => Ok(Some(id)),
+            None => Ok(sqlx::query_scalar("SELECT MAX(id) FROM api_credentials").fetch_one(&self.pool).await?)
+        }
+    }
+
+    pub async fn set_active(&self, label: &str) -> Result<i64> {
+        let id = self.require(label).await?;
+        sqlx::query("INSERT INTO active_profile (id, credential_id) VALUES (1, ?1) ON CONFLICT(id) DO UPDATE SET credential_id = excluded.credential_id")
+            .bind(id).execute(&self.pool).await?;
+        Ok(id)
+    }
+
+    pub async fn rename(&self, label: &str, new_label: &str) -> Result<()> {
+        let new_label = check_label(new_label)?;
+        let id = self.require(label).await?;
+        if self.id_of(new_label).await?.is_some_and(|other| other != id) {
+            return Err(anyhow!("Profile '{new_label}' đã tồn tại"));
+        }
+        sqlx::query("UPDATE api_credentials SET label = ?2 WHERE id = ?1").bind(id).bind(new_label)
+            .execute(&self.pool).await?;
         Ok(())
     }
 
-    pub async fn latest(&self) -> Result<Option<(String,String)>> {
-        if let Some(row) = sqlx::query(r#"SELECT api_key_enc, api_secret_enc FROM api_credentials ORDER BY id DESC LIMIT 1"#)
-            .fetch_optional(&self.pool).await? {
+    /// Delete a profile; returns its id. Its nickname links and sync state go with it,
+    /// its orders become unassigned (NULL account).
+    pub async fn delete(&self, label: &str) -> Result<i64> {
+        let id = self.require(label).await?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE active_profile SET credential_id = NULL WHERE credential_id = ?1").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM account_nicknames WHERE account_id = ?1").bind(id).execute(&mut *tx).await?;
+        // sync_state ids are "<account>:<BUY|SELL>"
+        sqlx::query("DELETE FROM sync_state WHERE id LIKE ?1 || ':%'").bind(id).execute(&mut *tx).await?;
+        sqlx::query("UPDATE orders SET account_id = NULL WHERE account_id = ?1").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM api_credentials WHERE id = ?1").bind(id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub async fn load(&self, id: i64) -> Result<Option<Credentials>> {
+        if let Some(row) = sqlx::query(r#"SELECT label, api_key_enc, api_secret_enc FROM api_credentials WHERE id = ?1"#)
+            .bind(id).fetch_optional(&self.pool).await? {
             let key_enc: Vec<u8> = row.get("api_key_enc");
             let sec_enc: Vec<u8> = row.get("api_secret_enc");
-            let key = String::from_utf8(self.crypto.decrypt(&key_enc)?)?;
-            let secret = String::from_utf8(self.crypto.decrypt(&sec_enc)?)?;
-            Ok(Some((key, secret)))
+            Ok(Some(Credentials {
+                profile_id: id,
+                label: row.get::<Option<String>,_>("label").unwrap_or_default(),
+                api_key: String::from_utf8(self.crypto.decrypt(&key_enc)?)?,
+                api_secret: String::from_utf8(self.crypto.decrypt(&sec_enc)?)?
+            }))
         } else { Ok(None) }
     }
+
+    pub async fn active(&self) -> Result<Option<Credentials>> {
+        match self.active_id().await? {
+            Some(id) => self.load(id).await,
+            None => Ok(None)
+        }
+    }
+
+    pub async fn touch(&self, id: i64, ts: i64) -> Result<()> {
+        sqlx::query("UPDATE api_credentials SET last_used_at = ?2 WHERE id = ?1").bind(id).bind(ts)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn id_of(&self, label: &str) -> Result<Option<i64>> {
+        // Older builds appended a row per save: the newest one with the label wins
+        Ok(sqlx::query_scalar("SELECT MAX(id) FROM api_credentials WHERE label = ?1").bind(label)
+            .fetch_one(&self.pool).await?)
+    }
+
+    pub async fn profile_id(&self, label: &str) -> Result<i64> {
+        self.require(label).await
+    }
+
+    async fn require(&self, label: &str) -> Result<i64> {
+        self.id_of(label).await?.ok_or_else(|| anyhow!("Không tìm thấy profile '{label}'"))
+    }
+}
+
+fn check_label(label: &str) -> Result<&str> {
+    let label = label.trim();
+    if label.is_empty() { return Err(anyhow!("Label không được để trống")); }
+    Ok(label)
 }
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Typed failures of the Binance C2C API, carried inside `anyhow::Error`
+/// (use `err.downcast_ref::<ApiError>()` to inspect).
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// Invalid key / signature / permissions (HTTP 401, codes -2014, -2015, -1022, -2008)
+    Auth(String),
+    /// HTTP 418: IP banned after ignoring 429s
+    IpBanned { retry_after: Option<Duration> },
+    /// HTTP 429 or code -1003
+    RateLimited { retry_after: Option<Duration> },
+    /// Code -1021: timestamp outside recvWindow (clock drift)
+    TimestampOutOfWindow(String),
+    /// Any other non-success body
+    Api { code: String, msg: String },
+    /// Non-2xx without a recognised body
+    Http { status: u16, body: String },
+    /// Connect / timeout / IO
+    Transport(String),
+    /// Body is not valid JSON
+    Decode(String)
+}
+
+impl ApiError {
+    /// Worth retrying with backoff
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::RateLimited { .. } | ApiError::Transport(_) => true,
+            // Retrying a ban blindly only extends it: wait only when Binance says how long
+            ApiError::IpBanned { retry_after } => retry_after.is_some(),
+            ApiError::Http { status, .. } => *status >= 500,
+            _ => false
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited { retry_after } | ApiError::IpBanned { retry_after } => *retry_after,
+            _ => None
+        }
+    }
+
+    /// Map a Binance error body (`{"code":-1021,"msg":"..."}`) to a typed error
+    pub fn from_body(status: u16, code: &str, msg: &str, retry_after: Option<Duration>) -> Self {
+        match (status, code) {
+            (418, _) => ApiError::IpBanned { retry_after },
+            (429, _) | (_, "-1003") => ApiError::RateLimited { retry_after },
+            (_, "-1021") => ApiError::TimestampOutOfWindow(msg.to_string()),
+            (401, _) | (_, "-1022") | (_, "-2008") | (_, "-2014") | (_, "-2015") => ApiError::Auth(msg.to_string()),
+            (s, _) if s >= 500 => ApiError::Http { status, body: msg.to_string() },
+            _ => ApiError::Api { code: code.to_string(), msg: msg.to_string() }
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Auth(m) => write!(f, "API auth failed: {m}"),
+            ApiError::IpBanned { retry_after } => write!(f, "IP banned by Binance (retry after {:?})", retry_after),
+            ApiError::RateLimited { retry_after } => write!(f, "Rate limited (retry after {:?})", retry_after),
+            ApiError::TimestampOutOfWindow(m) => write!(f, "Timestamp outside recvWindow: {m}"),
+            ApiError::Api { code, msg } => write!(f, "API error {code}: {msg}"),
+            ApiError::Http { status, body } => write!(f, "HTTP {status}: {body}"),
+            ApiError::Transport(m) => write!(f, "Network error: {m}"),
+            ApiError::Decode(m) => write!(f, "JSON parse error: {m}")
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
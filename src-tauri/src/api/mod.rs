@@ -0,0 +1,7 @@
+pub mod c2c_api_client;
+pub mod clients;
+pub mod credentials;
+pub mod error;
+pub mod rate_limit;
+pub mod sync_engine;
+pub mod sync_state;
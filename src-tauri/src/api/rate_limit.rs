@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Client-side token bucket in Binance request-weight units
+pub struct TokenBucket {
+    inner: Mutex<BucketState>,
+    capacity: f64,
+    refill_per_sec: f64
+}
+
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+    // Server reported weight near the limit: hold every request until this instant
+    paused_until: Option<Instant>
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, per_minute: u32) -> Self {
+        Self {
+            inner: Mutex::new(BucketState { tokens: capacity as f64, last: Instant::now(), paused_until: None }),
+            capacity: capacity as f64,
+            refill_per_sec: per_minute as f64 / 60.0
+        }
+    }
+
+    /// Wait until `cost` tokens are available and take them
+    pub async fn acquire(&self, cost: u32) {
+        let cost = (cost as f64).min(self.capacity);
+        loop {
+            let wait = {
+                let mut s = self.inner.lock().await;
+                let now = Instant::now();
+                match s.paused_until {
+                    Some(t) if t > now => t - now,
+                    _ => {
+                        s.paused_until = None;
+                        let elapsed = now.duration_since(s.last).as_secs_f64();
+                        s.tokens = (s.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+                        s.last = now;
+                        if s.tokens >= cost {
+                            s.tokens -= cost;
+                            return;
+                        }
+                        Duration::from_secs_f64((cost - s.tokens) / self.refill_per_sec)
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Stop issuing requests for `d` (Retry-After, or used weight close to the server limit)
+    pub async fn pause_for(&self, d: Duration) {
+        let mut s = self.inner.lock().await;
+        let until = Instant::now() + d;
+        if s.paused_until.is_none_or(|t| t < until) {
+            s.paused_until = Some(until);
+        }
+    }
+}
@@ -1,35 +1,199 @@
 use anyhow::Result;
 use chrono::{Utc, Duration};
+use serde::Serialize;
 use crate::api::c2c_api_client::C2CApiClient;
+use crate::api::sync_state::SyncStateRepo;
 use crate::orders::repo::OrderRepo;
+use crate::orders::store::OrderStore;
 
+const TRADE_TYPES: [&str; 2] = ["BUY", "SELL"];
+/// Re-read this much before the last synced end (orders created right at the window edge).
+/// The history endpoint filters on create time, so this does not catch status changes of
+/// older orders: `incremental_start` also reaches back to the oldest still-open order.
+const INCREMENTAL_OVERLAP_MS: i64 = 10 * 60 * 1000;
+/// listUserOrderHistory rejects ranges longer than 30 days
+pub const MAX_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+const PAGE_ROWS: u32 = 100;
+
+/// Progress of one completed time window
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProgress {
+    pub account_id: i64,
+    pub trade_type: String,
+    pub window_index: usize,
+    pub window_count: usize,
+    pub window_start: i64,
+    pub window_end: i64,
+    pub pages: u32,
+    pub orders: usize
+}
+
+pub type ProgressFn<'a> = &'a (dyn Fn(&SyncProgress) + Send + Sync);
+
+/// Syncs one merchant account (credential profile) into `orders`
 pub struct SyncEngine<'a> {
     pub client: &'a C2CApiClient,
-    pub repo: &'a OrderRepo
+    pub repo: &'a OrderRepo,
+    pub state: &'a SyncStateRepo,
+    pub account_id: i64,
+    progress: Option<ProgressFn<'a>>,
+    // Extension-side copy of the orders; its stages follow what the API wrote
+    store: Option<&'a OrderStore>
 }
 
 impl<'a> SyncEngine<'a> {
-    pub fn new(client: &'a C2CApiClient, repo: &'a OrderRepo) -> Self { Self { client, repo } }
+    pub fn new(client: &'a C2CApiClient, repo: &'a OrderRepo, state: &'a SyncStateRepo, account_id: i64) -> Self {
+        Self { client, repo, state, account_id, progress: None, store: None }
+    }
 
-    pub async fn force_initial_sync(&self, days: i64) -> Result<()> {
+    pub fn with_progress(mut self, f: ProgressFn<'a>) -> Self {
+        self.progress = Some(f);
+        self
+    }
+
+    pub fn with_store(mut self, store: &'a OrderStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub async fn force_initial_sync(&self, days: i64) -> Result<usize> {
         let now = Utc::now().timestamp_millis();
         let start = (Utc::now() - Duration::days(days)).timestamp_millis();
-        for trade in ["BUY", "SELL"] {
-            println!("[SYNC] Initial sync {trade} days={days}");
-            let mut page = 1;
-            loop {
-                let res = self.client.list_user_order_history(trade, start, now, page, 100).await?;
-                let data_arr = res.get("data")
-                    .and_then(|d| d.get("data"))
-                    .and_then(|x| x.as_array())
-                    .unwrap_or(&vec![]);
-                if data_arr.is_empty() && page == 1 { println!("[SYNC] No orders for {trade}"); }
-                for o in data_arr { self.repo.upsert_from_api(o, now).await?; }
-                println!("[SYNC] {trade} page {page} -> {} orders", data_arr.len());
-                if data_arr.len() < 100 { break; }
-                page += 1;
+        let mut total = 0;
+        for trade in TRADE_TYPES {
+            println!("[SYNC] Initial sync account={} {trade} days={days}", self.account_id);
+            total += self.sync_range(trade, start, now).await?;
+        }
+        Ok(total)
+    }
+
+    /// Resume each trade type from its stored window (minus overlap), or earlier if an
+    /// order created before that is still pending / paid / appealing.
+    /// Streams never synced before fall back to `fallback_days`.
+    pub async fn incremental_sync(&self, fallback_days: i64) -> Result<usize> {
+        let now = Utc::now().timestamp_millis();
+        let mut total = 0;
+        for trade in TRADE_TYPES {
+            let start = match self.state.get(&self.stream_id(trade)).await? {
+                Some(w) if w.last_end_timestamp > 0 => {
+                    let oldest_open = self.repo.oldest_open_order(self.account_id, trade).await?;
+                    incremental_start(w.last_end_timestamp, oldest_open, now)
+                }
+                _ => (Utc::now() - Duration::days(fallback_days)).timestamp_millis()
+            };
+            println!("[SYNC] Incremental account={} {trade} from {start}", self.account_id);
+            total += self.sync_range(trade, start, now).await?;
+        }
+        Ok(total)
+    }
+
+    /// Sync [start, end] window by window. `sync_state` advances after every
+    /// completed window so an interrupted backfill resumes where it stopped.
+    async fn sync_range(&self, trade: &str, start: i64, end: i64) -> Result<usize> {
+        let windows = split_windows(start, end, MAX_WINDOW_MS);
+        let mut total = 0;
+        for (i, (w_start, w_end)) in windows.iter().copied().enumerate() {
+            let (pages, orders) = self.sync_window(trade, w_start, w_end).await?;
+            total += orders;
+            self.state.save(&self.stream_id(trade), start, w_end, Utc::now().timestamp_millis()).await?;
+            let p = SyncProgress {
+                account_id: self.account_id,
+                trade_type: trade.to_string(),
+                window_index: i + 1,
+                window_count: windows.len(),
+                window_start: w_start,
+                window_end: w_end,
+                pages,
+                orders
+            };
+            println!("[SYNC] {trade} window {}/{} -> {orders} orders", p.window_index, p.window_count);
+            if let Some(f) = self.progress { f(&p); }
+        }
+        Ok(total)
+    }
+
+    /// `sync_state` row id: one stream per account and trade type
+    fn stream_id(&self, trade: &str) -> String {
+        format!("{}:{trade}", self.account_id)
+    }
+
+    async fn sync_window(&self, trade: &str, start: i64, end: i64) -> Result<(u32, usize)> {
+        let now = Utc::now().timestamp_millis();
+        let empty = vec![];
+        let mut page = 1;
+        let mut total = 0;
+        loop {
+            let res = self.client.list_user_order_history(trade, start, end, page, PAGE_ROWS).await
+                .map_err(|e| e.context(format!("{trade} window {start}..{end} page {page}")))?;
+            // sapi returns `data: [...]`; keep accepting the nested `data.data` shape too
+            let data_arr = res.get("data")
+                .and_then(|d| d.get("data").or(Some(d)))
+                .and_then(|x| x.as_array())
+                .unwrap_or(&empty);
+            for o in data_arr {
+                let stage = self.repo.upsert_from_api(o, self.account_id, now).await?;
+                if let (Some(store), Some(stage), Some(no)) = (self.store, stage, o.get("orderNumber").and_then(|x| x.as_str())) {
+                    store.observe_api_stage(no, stage).await;
+                }
             }
+            total += data_arr.len();
+            if data_arr.len() < PAGE_ROWS as usize { break; }
+            page += 1;
         }
-        Ok(())
+        Ok((page, total))
+    }
+}
+
+/// Start of an incremental run: the cursor minus overlap, moved back to the creation of the
+/// oldest open order so its final status is fetched, but never more than one window back
+pub fn incremental_start(last_end: i64, oldest_open: Option<i64>, now: i64) -> i64 {
+    let from_cursor = (last_end - INCREMENTAL_OVERLAP_MS).max(0);
+    match oldest_open {
+        Some(created) => from_cursor.min(created.max(now - MAX_WINDOW_MS)),
+        None => from_cursor
+    }
+}
+
+/// Split [start, end] into consecutive, non-overlapping windows of at most `max_ms`
+pub fn split_windows(start: i64, end: i64, max_ms: i64) -> Vec<(i64, i64)> {
+    let mut out = Vec::new();
+    let mut cur = start;
+    while cur < end {
+        let w_end = (cur + max_ms).min(end);
+        out.push((cur, w_end));
+        cur = w_end + 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 100 * MAX_WINDOW_MS;
+
+    #[test]
+    fn start_from_cursor_minus_overlap() {
+        assert_eq!(incremental_start(NOW - 1_000, None, NOW), NOW - 1_000 - INCREMENTAL_OVERLAP_MS);
+        assert_eq!(incremental_start(1_000, None, NOW), 0);
+    }
+
+    #[test]
+    fn start_reaches_back_to_oldest_open_order() {
+        let created = NOW - 5 * 24 * 60 * 60 * 1000;
+        assert_eq!(incremental_start(NOW - 1_000, Some(created), NOW), created);
+        // Open order newer than the cursor: cursor wins
+        assert_eq!(incremental_start(NOW - 2 * MAX_WINDOW_MS, Some(created), NOW), NOW - 2 * MAX_WINDOW_MS - INCREMENTAL_OVERLAP_MS);
+    }
+
+    #[test]
+    fn open_order_lookback_clamped_to_one_window() {
+        assert_eq!(incremental_start(NOW - 1_000, Some(NOW - 3 * MAX_WINDOW_MS), NOW), NOW - MAX_WINDOW_MS);
+    }
+
+    #[test]
+    fn windows_cover_range_without_overlap() {
+        assert_eq!(split_windows(0, 25, 10), vec![(0, 10), (11, 21), (22, 25)]);
+        assert!(split_windows(5, 5, 10).is_empty());
     }
 }
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use sqlx::{SqlitePool, Row};
+use sqlx::sqlite::SqliteRow;
+use serde::Serialize;
+
+/// Last successfully synced window for one sync stream (row id = `{account_id}:{trade type}`)
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncWindow {
+    pub id: String,
+    pub last_start_timestamp: i64,
+    pub last_end_timestamp: i64,
+    pub last_complete_ts: i64
+}
+
+pub struct SyncStateRepo { pool: SqlitePool }
+
+impl SyncStateRepo {
+    pub fn new(pool: SqlitePool) -> Self { Self { pool } }
+
+    pub async fn get(&self, id: &str) -> Result<Option<SyncWindow>> {
+        let row = sqlx::query(r#"SELECT id, last_start_timestamp, last_end_timestamp, last_complete_ts FROM sync_state WHERE id = ?1"#)
+            .bind(id).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(window_from_row))
+    }
+
+    pub async fn list(&self) -> Result<Vec<SyncWindow>> {
+        let rows = sqlx::query(r#"SELECT id, last_start_timestamp, last_end_timestamp, last_complete_ts FROM sync_state ORDER BY id"#)
+            .fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(window_from_row).collect())
+    }
+
+    pub async fn save(&self, id: &str, start: i64, end: i64, completed_at: i64) -> Result<()> {
+        sqlx::query(r#"INSERT INTO sync_state (id, last_start_timestamp, last_end_timestamp, last_complete_ts) VALUES (?1,?2,?3,?4) ON CONFLICT(id) DO UPDATE SET last_start_timestamp=excluded.last_start_timestamp, last_end_timestamp=excluded.last_end_timestamp, last_complete_ts=excluded.last_complete_ts"#)
+            .bind(id).bind(start).bind(end).bind(completed_at)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+fn window_from_row(r: &SqliteRow) -> SyncWindow {
+    SyncWindow {
+        id: r.get("id"),
+        last_start_timestamp: r.get::<Option<i64>,_>("last_start_timestamp").unwrap_or(0),
+        last_end_timestamp: r.get::<Option<i64>,_>("last_end_timestamp").unwrap_or(0),
+        last_complete_ts: r.get::<Option<i64>,_>("last_complete_ts").unwrap_or(0),
+    }
+}
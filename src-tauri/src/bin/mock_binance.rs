@@ -0,0 +1,279 @@
+//! Local mock of the Binance endpoints used by `C2CApiClient`, for offline / CI sync checks.
+//!
+//! Run:   cargo run --bin mock_binance -- --addr 127.0.0.1:9900 --orders 450 --days 180
+//! App:   P2P_API_BASE_URL=http://127.0.0.1:9900 npm run tauri:dev
+//!
+//! Serves `/api/v3/time` and `/sapi/v1/c2c/orderMatch/listUserOrderHistory`, verifies the
+//! API key header and HMAC signature, enforces recvWindow and the 30-day range limit, and can
+//! inject failures (`--fail-every N` -> HTTP 500, `--rate-limit-every N` -> HTTP 429 + Retry-After,
+//! `--clock-skew-ms N` -> shifted server time).
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MAX_RANGE_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+const DEFAULT_RECV_WINDOW_MS: i64 = 5000;
+
+#[derive(Clone)]
+pub struct MockConfig {
+    pub addr: String,
+    pub api_key: String,
+    pub api_secret: String,
+    pub orders: usize,
+    pub days: i64,
+    pub fail_every: u64,
+    pub rate_limit_every: u64,
+    pub clock_skew_ms: i64
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9900".into(),
+            api_key: "mock-key".into(),
+            api_secret: "mock-secret".into(),
+            orders: 250,
+            days: 90,
+            fail_every: 0,
+            rate_limit_every: 0,
+            clock_skew_ms: 0
+        }
+    }
+}
+
+struct MockState {
+    cfg: MockConfig,
+    // (trade_type, create_time_ms, order json), newest first
+    fixtures: Vec<(String, i64, Value)>,
+    requests: AtomicU64,
+    // (minute index, requests in that minute) for the used-weight header
+    weight_1m: Mutex<(i64, u64)>
+}
+
+impl MockState {
+    fn bump_weight(&self) -> u64 {
+        let minute = now_ms() / 60_000;
+        let mut w = self.weight_1m.lock().unwrap();
+        if w.0 != minute { *w = (minute, 0); }
+        w.1 += 1;
+        w.1
+    }
+}
+
+struct Reply {
+    status: u16,
+    body: Value,
+    headers: Vec<(String, String)>
+}
+
+impl Reply {
+    fn json(status: u16, body: Value) -> Self { Self { status, body, headers: vec![] } }
+    fn error(status: u16, code: i64, msg: &str) -> Self { Self::json(status, json!({ "code": code, "msg": msg })) }
+}
+
+fn parse_args() -> Result<MockConfig> {
+    let mut cfg = MockConfig::default();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut it = args.iter();
+    while let Some(flag) = it.next() {
+        let val = it.next().ok_or_else(|| anyhow!("Missing value for {flag}"))?;
+        match flag.as_str() {
+            "--addr" => cfg.addr = val.clone(),
+            "--key" => cfg.api_key = val.clone(),
+            "--secret" => cfg.api_secret = val.clone(),
+            "--orders" => cfg.orders = val.parse()?,
+            "--days" => cfg.days = val.parse()?,
+            "--fail-every" => cfg.fail_every = val.parse()?,
+            "--rate-limit-every" => cfg.rate_limit_every = val.parse()?,
+            "--clock-skew-ms" => cfg.clock_skew_ms = val.parse()?,
+            _ => return Err(anyhow!("Unknown flag {flag}"))
+        }
+    }
+    Ok(cfg)
+}
+
+/// Orders spread evenly over the last `days`, alternating BUY / SELL
+fn build_fixtures(cfg: &MockConfig, now: i64) -> Vec<(String, i64, Value)> {
+    let span = cfg.days * 24 * 60 * 60 * 1000;
+    let step = if cfg.orders > 0 { span / cfg.orders as i64 } else { span };
+    let statuses = ["COMPLETED", "COMPLETED", "COMPLETED", "CANCELLED", "BUYER_PAYED", "TRADING"];
+    (0..cfg.orders).map(|i| {
+        let trade = if i % 2 == 0 { "BUY" } else { "SELL" };
+        let create = now - step * i as i64 - 1000;
+        let amount = 100 + (i % 50) as i64;
+        let order = json!({
+            "orderNumber": format!("2{:018}", i),
+            "advNo": format!("1{:018}", i % 7),
+            "tradeType": trade,
+            "asset": "USDT",
+            "fiat": "VND",
+            "fiatSymbol": "₫",
+            "amount": format!("{amount}.00000000"),
+            "totalPrice": format!("{}.00", amount * 25_500),
+            "unitPrice": "25500.00",
+            "orderStatus": statuses[i % statuses.len()],
+            "createTime": create,
+            "commission": "0",
+            "counterPartNickName": format!("mock_user_{}", i % 13),
+            "advertisementRole": "TAKER"
+        });
+        (trade.to_string(), create, order)
+    }).collect()
+}
+
+fn now_ms() -> i64 { chrono::Utc::now().timestamp_millis() }
+
+fn sign(secret: &str, query: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(query.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&').find_map(|kv| {
+        let (k, v) = kv.split_once('=')?;
+        (k == key).then_some(v)
+    })
+}
+
+/// Check API key, signature and timestamp like the real SAPI does
+fn verify_signed(state: &MockState, query: &str, headers: &[(String, String)]) -> Result<(), Reply> {
+    let key = headers.iter().find(|(k, _)| k == "x-mbx-apikey").map(|(_, v)| v.as_str());
+    if key != Some(state.cfg.api_key.as_str()) {
+        return Err(Reply::error(401, -2015, "Invalid API-key, IP, or permissions for action."));
+    }
+    let (payload, signature) = query.rsplit_once("&signature=")
+        .ok_or_else(|| Reply::error(400, -1102, "Mandatory parameter 'signature' was not sent."))?;
+    if sign(&state.cfg.api_secret, payload) != signature {
+        return Err(Reply::error(400, -1022, "Signature for this request is not valid."));
+    }
+    let ts: i64 = query_param(payload, "timestamp").and_then(|v| v.parse().ok())
+        .ok_or_else(|| Reply::error(400, -1102, "Mandatory parameter 'timestamp' was not sent."))?;
+    let recv_window: i64 = query_param(payload, "recvWindow").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_RECV_WINDOW_MS);
+    let server_now = now_ms() + state.cfg.clock_skew_ms;
+    if ts > server_now + 1000 || server_now - ts > recv_window {
+        return Err(Reply::error(400, -1021, "Timestamp for this request is outside of the recvWindow."));
+    }
+    Ok(())
+}
+
+fn list_history(state: &MockState, query: &str) -> Reply {
+    let num = |k: &str| query_param(query, k).and_then(|v| v.parse::<i64>().ok());
+    let trade = query_param(query, "tradeType");
+    let end = num("endTimestamp").unwrap_or_else(now_ms);
+    let start = num("startTimestamp").unwrap_or(end - MAX_RANGE_MS);
+    if end - start > MAX_RANGE_MS {
+        return Reply::error(400, -1127, "More than 30 days between startTimestamp and endTimestamp.");
+    }
+    let page = num("page").unwrap_or(1).max(1) as usize;
+    let rows = num("rows").unwrap_or(100).clamp(1, 100) as usize;
+    let matching: Vec<&Value> = state.fixtures.iter()
+        .filter(|(t, ts, _)| trade.is_none_or(|x| x == t) && *ts >= start && *ts <= end)
+        .map(|(_, _, o)| o)
+        .collect();
+    let data: Vec<&Value> = matching.iter().skip((page - 1) * rows).take(rows).copied().collect();
+    Reply::json(200, json!({ "code": "000000", "message": "success", "data": data, "total": matching.len(), "success": true }))
+}
+
+fn route(state: &MockState, method: &str, target: &str, headers: &[(String, String)]) -> Reply {
+    let n = state.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if method != "GET" { return Reply::error(405, -1000, "Method not allowed"); }
+    if path == "/api/v3/time" {
+        return Reply::json(200, json!({ "serverTime": now_ms() + state.cfg.clock_skew_ms }));
+    }
+    if state.cfg.fail_every > 0 && n.is_multiple_of(state.cfg.fail_every) {
+        return Reply::error(500, -1000, "Injected internal error");
+    }
+    if state.cfg.rate_limit_every > 0 && n.is_multiple_of(state.cfg.rate_limit_every) {
+        let mut r = Reply::error(429, -1003, "Injected rate limit");
+        r.headers.push(("Retry-After".into(), "1".into()));
+        return r;
+    }
+    if let Err(r) = verify_signed(state, query, headers) { return r; }
+    match path {
+        "/sapi/v1/c2c/orderMatch/listUserOrderHistory" => list_history(state, query),
+        _ => Reply::error(404, -1000, "Not found")
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<MockState>) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 { return Ok(()); }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > 64 * 1024 { return Err(anyhow!("Request header too large")); }
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let headers: Vec<(String, String)> = lines
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let reply = route(&state, method, target, &headers);
+    println!("[MOCK] {method} {} -> {}", target.split('?').next().unwrap_or(""), reply.status);
+
+    let body = reply.body.to_string();
+    let used_weight = state.bump_weight();
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\nX-SAPI-USED-IP-WEIGHT-1M: {}\r\n",
+        reply.status, reason(reply.status), body.len(), used_weight
+    );
+    for (k, v) in &reply.headers { out.push_str(&format!("{k}: {v}\r\n")); }
+    out.push_str("\r\n");
+    out.push_str(&body);
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        _ => "Internal Server Error"
+    }
+}
+
+/// Serve on an already bound listener (`cfg.addr` is ignored); tests bind port 0
+pub async fn serve(listener: TcpListener, cfg: MockConfig) -> Result<()> {
+    let fixtures = build_fixtures(&cfg, now_ms());
+    let state = Arc::new(MockState { cfg, fixtures, requests: AtomicU64::new(0), weight_1m: Mutex::new((0, 0)) });
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let st = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, st).await {
+                eprintln!("[MOCK] Connection error: {e}");
+            }
+        });
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cfg = parse_args()?;
+    println!("[MOCK] Listening on http://{} ({} orders over {} days, key={})", cfg.addr, cfg.orders, cfg.days, cfg.api_key);
+    let listener = TcpListener::bind(&cfg.addr).await?;
+    serve(listener, cfg).await
+}
@@ -0,0 +1,21 @@
+//! Regenerate the extension bridge JSON Schemas from the Rust protocol types (`ws::protocol`).
+//!
+//! Run:   cargo run --bin ws_schema            (writes p2p-extension/schema/)
+//!        cargo run --bin ws_schema -- <dir>
+//!
+//! Commit the output together with any protocol change.
+
+use std::path::PathBuf;
+use anyhow::Result;
+
+fn main() -> Result<()> {
+    let dir = std::env::args().nth(1).map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../p2p-extension/schema")));
+    std::fs::create_dir_all(&dir)?;
+    for (name, schema) in tauri_app_lib::ws_protocol_schemas() {
+        let path = dir.join(name);
+        std::fs::write(&path, schema)?;
+        println!("wrote {}", path.display());
+    }
+    Ok(())
+}
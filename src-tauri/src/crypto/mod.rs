@@ -1,16 +1,141 @@
-use anyhow::Result;
+use std::sync::{Arc, RwLock};
+use anyhow::{Result, anyhow};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, KeyInit};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use serde::Serialize;
 
-// Dummy crypto context using base64 (NOT secure). Replace with real AEAD + KDF later.
-pub struct CryptoCtx;
+pub mod vault;
+
+// Ciphertext layout:
+//   v1: MAGIC (3) | 0x01 | nonce (24) | ciphertext+tag            (implicitly key #1)
+//   v2: MAGIC (3) | 0x02 | key id (u32 BE) | nonce (24) | ciphertext+tag
+const MAGIC: &[u8; 3] = b"P2E";
+const FORMAT_V1: u8 = 1;
+const FORMAT_V2: u8 = 2;
+const NONCE_LEN: usize = 24;
+const KEY_ID_LEN: usize = 4;
+
+/// Argon2id cost parameters, stored next to the salt so they can be raised later
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32
+}
+
+impl Default for KdfParams {
+    fn default() -> Self { Self { m_cost: 64 * 1024, t_cost: 3, p_cost: 1 } }
+}
+
+pub fn derive_key(password: &str, salt: &[u8], p: &KdfParams) -> Result<[u8; 32]> {
+    let params = Params::new(p.m_cost, p.t_cost, p.p_cost, Some(32)).map_err(|e| anyhow!("KDF params: {e}"))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("KDF failed: {e}"))?;
+    Ok(key)
+}
+
+/// Key id and payload offset of a sealed value, None for anything else (legacy plaintext)
+fn parse_header(enc: &[u8]) -> Option<(u32, usize)> {
+    if enc.len() < MAGIC.len() + 1 || &enc[..MAGIC.len()] != MAGIC { return None; }
+    let body = MAGIC.len() + 1;
+    let (key_id, nonce_at) = match enc[MAGIC.len()] {
+        FORMAT_V1 => (1, body),
+        FORMAT_V2 if enc.len() >= body + KEY_ID_LEN => {
+            let id = u32::from_be_bytes(enc[body..body + KEY_ID_LEN].try_into().ok()?);
+            (id, body + KEY_ID_LEN)
+        }
+        _ => return None
+    };
+    (enc.len() >= nonce_at + NONCE_LEN).then_some((key_id, nonce_at))
+}
+
+/// True if `enc` carries our ciphertext header (vs. legacy plaintext)
+pub fn is_sealed(enc: &[u8]) -> bool {
+    parse_header(enc).is_some()
+}
+
+/// Id of the master key a value was sealed with
+pub fn key_id_of(enc: &[u8]) -> Option<u32> {
+    parse_header(enc).map(|(id, _)| id)
+}
+
+/// A derived master key and the id it is tagged with in every ciphertext
+#[derive(Clone)]
+pub struct MasterKey {
+    id: u32,
+    cipher: XChaCha20Poly1305
+}
+
+impl MasterKey {
+    pub fn new(id: u32, key: &[u8; 32]) -> Self {
+        Self { id, cipher: XChaCha20Poly1305::new(key.into()) }
+    }
+
+    pub fn id(&self) -> u32 { self.id }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ct = self.cipher.encrypt(&nonce, plain).map_err(|_| anyhow!("Encryption failed"))?;
+        let mut out = Vec::with_capacity(MAGIC.len() + 1 + KEY_ID_LEN + NONCE_LEN + ct.len());
+        out.extend_from_slice(MAGIC);
+        out.push(FORMAT_V2);
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ct);
+        Ok(out)
+    }
+
+    pub fn open(&self, enc: &[u8]) -> Result<Vec<u8>> {
+        let (key_id, nonce_at) = parse_header(enc).ok_or_else(|| anyhow!("Not an encrypted value"))?;
+        if key_id != self.id {
+            return Err(anyhow!("Value is sealed with key #{key_id}, active key is #{}", self.id));
+        }
+        let nonce = XNonce::from_slice(&enc[nonce_at..nonce_at + NONCE_LEN]);
+        self.cipher.decrypt(nonce, &enc[nonce_at + NONCE_LEN..]).map_err(|_| anyhow!("Decryption failed (wrong key or corrupted data)"))
+    }
+}
+
+/// XChaCha20-Poly1305 context. Cheap to clone; every clone shares the same
+/// key slot, which stays empty until the vault is unlocked.
+#[derive(Clone, Default)]
+pub struct CryptoCtx {
+    key: Arc<RwLock<Option<MasterKey>>>
+}
 
 impl CryptoCtx {
-    pub fn new_dummy() -> Self { CryptoCtx }
+    pub fn locked() -> Self { Self::default() }
+
+    pub fn set_key(&self, key: MasterKey) {
+        *self.key.write().unwrap() = Some(key);
+    }
+
+    pub fn lock(&self) {
+        *self.key.write().unwrap() = None;
+    }
+
+    /// Empty the key slot and hand back the key (rotation keeps writers out meanwhile)
+    fn take(&self) -> Option<MasterKey> {
+        self.key.write().unwrap().take()
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.read().unwrap().is_some()
+    }
+
+    pub fn key_id(&self) -> Option<u32> {
+        self.key.read().unwrap().as_ref().map(|k| k.id)
+    }
+
     pub fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>> {
-        Ok(base64::encode(plain).into_bytes())
+        let guard = self.key.read().unwrap();
+        guard.as_ref().ok_or_else(|| anyhow!("Vault is locked"))?.seal(plain)
     }
+
     pub fn decrypt(&self, enc: &[u8]) -> Result<Vec<u8>> {
-        let s = String::from_utf8_lossy(enc);
-        let decoded = base64::decode(&*s)?;
-        Ok(decoded)
+        let guard = self.key.read().unwrap();
+        guard.as_ref().ok_or_else(|| anyhow!("Vault is locked"))?.open(enc)
     }
 }
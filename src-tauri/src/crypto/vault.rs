@@ -0,0 +1,186 @@
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool, Row};
+
+use super::{derive_key, is_sealed, key_id_of, CryptoCtx, KdfParams, MasterKey};
+
+const VERIFIER_PLAIN: &[u8] = b"p2p-vault-check";
+const SALT_LEN: usize = 16;
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Every column written through `CryptoCtx`, keyed by the table's `id`.
+/// Key rotation re-encrypts all of them; add new encrypted columns here.
+const ENCRYPTED_COLUMNS: &[(&str, &[&str])] = &[
+    ("api_credentials", &["api_key_enc", "api_secret_enc"]),
+    ("order_payment_detail", &["account_name", "account_no", "bank_name", "sub_bank", "qr_code_url"]),
+];
+
+#[derive(Debug, Serialize)]
+pub struct VaultStatus {
+    pub initialized: bool,
+    pub unlocked: bool,
+    pub key_id: Option<u32>
+}
+
+/// Master-password lifecycle: first-time setup, unlock at startup, lock.
+pub struct Vault {
+    pool: SqlitePool,
+    crypto: CryptoCtx
+}
+
+impl Vault {
+    pub fn new(pool: SqlitePool, crypto: CryptoCtx) -> Self { Self { pool, crypto } }
+
+    pub async fn status(&self) -> Result<VaultStatus> {
+        let n: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM vault_meta").fetch_one(&self.pool).await?;
+        Ok(VaultStatus { initialized: n > 0, unlocked: self.crypto.is_unlocked(), key_id: self.crypto.key_id() })
+    }
+
+    /// Create the vault and re-encrypt credentials left by the old base64 scheme
+    pub async fn setup(&self, password: &str) -> Result<()> {
+        check_password(password)?;
+        if self.status().await?.initialized { return Err(anyhow!("Vault already initialized")); }
+
+        let params = KdfParams::default();
+        let salt = new_salt();
+        let key = MasterKey::new(1, &derive_key_blocking(password, salt.to_vec(), params).await?);
+        let verifier = key.seal(VERIFIER_PLAIN)?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(r#"INSERT INTO vault_meta (id, kdf, salt, m_cost, t_cost, p_cost, verifier, created_at) VALUES (1, 'argon2id', ?1, ?2, ?3, ?4, ?5, ?6)"#)
+            .bind(&salt[..]).bind(params.m_cost as i64).bind(params.t_cost as i64).bind(params.p_cost as i64)
+            .bind(verifier).bind(Utc::now().timestamp_millis())
+            .execute(&mut *tx).await?;
+
+        let legacy = sqlx::query("SELECT id, api_key_enc, api_secret_enc FROM api_credentials").fetch_all(&mut *tx).await?;
+        for row in &legacy {
+            let id: i64 = row.get("id");
+            let key_enc = key.seal(&decode_legacy(row.get("api_key_enc"))?)?;
+            let sec_enc = key.seal(&decode_legacy(row.get("api_secret_enc"))?)?;
+            sqlx::query("UPDATE api_credentials SET api_key_enc = ?2, api_secret_enc = ?3 WHERE id = ?1")
+                .bind(id).bind(key_enc).bind(sec_enc)
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        if !legacy.is_empty() { println!("[VAULT] Re-encrypted {} legacy credential rows", legacy.len()); }
+
+        self.crypto.set_key(key);
+        Ok(())
+    }
+
+    pub async fn unlock(&self, password: &str) -> Result<()> {
+        let key = self.verify_password(password).await?;
+        self.crypto.set_key(key);
+        Ok(())
+    }
+
+    /// Switch to a key derived from `new_password` (fresh salt, next key id) and
+    /// re-encrypt every encrypted column in one transaction. Any failure rolls the
+    /// whole rotation back and the current key stays active.
+    pub async fn rotate_master_key(&self, current_password: &str, new_password: &str) -> Result<u32> {
+        check_password(new_password)?;
+        let old = self.verify_password(current_password).await?;
+        let params = KdfParams::default();
+        let salt = new_salt();
+        let new = MasterKey::new(old.id() + 1, &derive_key_blocking(new_password, salt.to_vec(), params).await?);
+
+        // Nothing else may seal with the old key while rows are being rewritten
+        let active = self.crypto.take();
+        let res = self.reencrypt_all(&old, &new, &salt, params).await;
+        match res {
+            Ok(n) => {
+                self.crypto.set_key(new.clone());
+                println!("[VAULT] Rotated master key #{} -> #{} ({n} values re-encrypted)", old.id(), new.id());
+                Ok(new.id())
+            }
+            Err(e) => {
+                if let Some(k) = active { self.crypto.set_key(k); }
+                Err(e.context("Key rotation rolled back"))
+            }
+        }
+    }
+
+    async fn reencrypt_all(&self, old: &MasterKey, new: &MasterKey, salt: &[u8], params: KdfParams) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE vault_meta SET salt = ?1, m_cost = ?2, t_cost = ?3, p_cost = ?4, verifier = ?5 WHERE id = 1")
+            .bind(salt).bind(params.m_cost as i64).bind(params.t_cost as i64).bind(params.p_cost as i64)
+            .bind(new.seal(VERIFIER_PLAIN)?)
+            .execute(&mut *tx).await?;
+        let mut n = 0;
+        for (table, columns) in ENCRYPTED_COLUMNS {
+            for column in *columns {
+                n += reencrypt_column(&mut tx, table, column, old, new).await
+                    .with_context(|| format!("Re-encrypting {table}.{column}"))?;
+            }
+        }
+        // Dropping the transaction on any `?` above rolls everything back
+        tx.commit().await?;
+        Ok(n)
+    }
+
+    /// Derive the key for `password` and check it against the stored verifier
+    async fn verify_password(&self, password: &str) -> Result<MasterKey> {
+        let row = sqlx::query("SELECT salt, m_cost, t_cost, p_cost, verifier FROM vault_meta WHERE id = 1")
+            .fetch_optional(&self.pool).await?
+            .ok_or_else(|| anyhow!("Vault not initialized"))?;
+        let salt: Vec<u8> = row.get("salt");
+        let params = KdfParams {
+            m_cost: row.get::<i64,_>("m_cost") as u32,
+            t_cost: row.get::<i64,_>("t_cost") as u32,
+            p_cost: row.get::<i64,_>("p_cost") as u32
+        };
+        let verifier: Vec<u8> = row.get("verifier");
+        let key_id = key_id_of(&verifier).ok_or_else(|| anyhow!("Corrupted vault verifier"))?;
+        let key = MasterKey::new(key_id, &derive_key_blocking(password, salt, params).await?);
+        match key.open(&verifier) {
+            Ok(v) if v == VERIFIER_PLAIN => Ok(key),
+            _ => Err(anyhow!("Sai master password"))
+        }
+    }
+
+    pub fn lock(&self) {
+        self.crypto.lock();
+    }
+}
+
+async fn reencrypt_column(conn: &mut SqliteConnection, table: &str, column: &str, old: &MasterKey, new: &MasterKey) -> Result<usize> {
+    let rows = sqlx::query(&format!("SELECT id, {column} AS v FROM {table} WHERE {column} IS NOT NULL"))
+        .fetch_all(&mut *conn).await?;
+    for row in &rows {
+        let enc: Vec<u8> = row.get("v");
+        // Plaintext left from before field encryption gets sealed too
+        let plain = if is_sealed(&enc) { old.open(&enc)? } else { enc };
+        sqlx::query(&format!("UPDATE {table} SET {column} = ?2 WHERE id = ?1"))
+            .bind(row.get::<i64,_>("id")).bind(new.seal(&plain)?)
+            .execute(&mut *conn).await?;
+    }
+    Ok(rows.len())
+}
+
+fn check_password(password: &str) -> Result<()> {
+    if password.len() < MIN_PASSWORD_LEN {
+        return Err(anyhow!("Master password phải có ít nhất {MIN_PASSWORD_LEN} ký tự"));
+    }
+    Ok(())
+}
+
+fn new_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Argon2id is deliberately slow; keep it off the async workers
+async fn derive_key_blocking(password: &str, salt: Vec<u8>, params: KdfParams) -> Result<[u8; 32]> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || derive_key(&password, &salt, &params)).await?
+}
+
+/// Rows written by the pre-vault dummy scheme are plain base64 text
+fn decode_legacy(enc: Vec<u8>) -> Result<Vec<u8>> {
+    let s = String::from_utf8(enc)?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(s.trim())?)
+}
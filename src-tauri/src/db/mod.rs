@@ -1,33 +1,216 @@
-use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
-use anyhow::Result;
-use std::path::Path;
+use sqlx::{SqlitePool, Row, sqlite::{SqliteConnectOptions, SqlitePoolOptions}};
+use anyhow::{Result, Context, bail};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// DB file name inside the app data dir (and the old CWD location)
+pub const DB_FILE: &str = "p2p_app.db";
+/// Full path override, e.g. a portable install or a second copy of the app
+pub const DB_PATH_ENV: &str = "P2P_DB_PATH";
+// SQLite side files that belong to the DB and must move with it
+const SIDECARS: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+/// Schema migrations, applied in order. Never edit a file once it has shipped:
+/// its checksum is recorded in `schema_migrations` and a mismatch aborts startup.
+/// Add a new numbered file instead.
+const MIGRATIONS: &[(i64, &str, &str)] = &[
+    (1, "001_init.sql", include_str!("../../migrations/001_init.sql")),
+    (2, "002_order_events.sql", include_str!("../../migrations/002_order_events.sql")),
+    (3, "003_vault.sql", include_str!("../../migrations/003_vault.sql")),
+    (4, "004_credential_profiles.sql", include_str!("../../migrations/004_credential_profiles.sql")),
+    (5, "005_accounts.sql", include_str!("../../migrations/005_accounts.sql")),
+    (6, "006_nickname_source.sql", include_str!("../../migrations/006_nickname_source.sql")),
+    (7, "007_decimal_amounts.sql", include_str!("../../migrations/007_decimal_amounts.sql")),
+    (8, "008_ws_pairing.sql", include_str!("../../migrations/008_ws_pairing.sql")),
+];
+
+/// Last migration the old runner (split on ';', re-run every boot) applied: it only ever
+/// ran 001_init.sql. DBs created by it have no `schema_migrations` table and get adopted
+/// up to here; 002+ then run as normal migrations.
+const LEGACY_BASELINE: i64 = 1;
 
 pub struct Db {
     pool: SqlitePool
 }
 
+/// `$P2P_DB_PATH` if set, else `db.path` from settings, else `<app data dir>/p2p_app.db`.
+pub fn resolve_path(data_dir: &Path, configured: Option<&Path>) -> PathBuf {
+    match std::env::var_os(DB_PATH_ENV) {
+        Some(p) if !p.is_empty() => PathBuf::from(p),
+        _ => configured.map(Path::to_path_buf).unwrap_or_else(|| data_dir.join(DB_FILE)),
+    }
+}
+
 impl Db {
-    pub async fn init(db_path: &str) -> Result<Self> {
-        let exists = Path::new(db_path).exists();
-        let url = format!("sqlite://{}?mode=rwc", db_path);
+    pub async fn init(db_path: &Path) -> Result<Self> {
+        if let Some(dir) = db_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create database directory {}", dir.display()))?;
+        }
+        Self::adopt_cwd_db(db_path)?;
+        let exists = db_path.exists();
+        // Filename options instead of a sqlite:// URL: app data paths can contain spaces / backslashes
+        let options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .create_if_missing(true);
         let pool = SqlitePoolOptions::new()
             .max_connections(5)
-            .connect(&url).await?;
-        if !exists { println!("[DB] Creating new database at {db_path}"); }
-        Self::run_migrations(&pool).await?;
+            .connect_with(options).await
+            .with_context(|| format!("Cannot open database {}", db_path.display()))?;
+        if !exists { println!("[DB] Creating new database at {}", db_path.display()); }
+        if let Err(e) = Self::run_migrations(&pool).await {
+            eprintln!("[DB] Migration failed: {e:#}");
+            return Err(e);
+        }
         Ok(Self { pool })
     }
 
+    /// Older builds kept the DB in the working directory. Move it (with its WAL/SHM files)
+    /// to `db_path` on first run; never overwrite an existing DB at the new location.
+    fn adopt_cwd_db(db_path: &Path) -> Result<()> {
+        let old = match std::env::current_dir() {
+            Ok(cwd) => cwd.join(DB_FILE),
+            Err(_) => return Ok(()),
+        };
+        if !old.is_file() { return Ok(()); }
+        if db_path.exists() {
+            let same = std::fs::canonicalize(&old).ok() == std::fs::canonicalize(db_path).ok();
+            if !same { println!("[DB] Ignoring {} - database already exists at {}", old.display(), db_path.display()); }
+            return Ok(());
+        }
+        for suffix in std::iter::once("").chain(SIDECARS) {
+            let from = with_suffix(&old, suffix);
+            if !from.exists() { continue; }
+            move_file(&from, &with_suffix(db_path, suffix))
+                .with_context(|| format!("Cannot move {} to {}", from.display(), db_path.display()))?;
+        }
+        println!("[DB] Moved database {} -> {}", old.display(), db_path.display());
+        Ok(())
+    }
+
     async fn run_migrations(pool: &SqlitePool) -> Result<()> {
-        let sql = include_str!("../../migrations/001_init.sql");
-        for statement in sql.split(';') {
-            let stmt = statement.trim();
-            if !stmt.is_empty() {
-                sqlx::query(stmt).execute(pool).await?;
+        let legacy = !Self::table_exists(pool, "schema_migrations").await?
+            && Self::table_exists(pool, "orders").await?;
+        sqlx::query("CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at INTEGER NOT NULL
+            )")
+            .execute(pool).await?;
+        if legacy { Self::adopt_legacy(pool).await?; }
+
+        let applied: Vec<(i64, String, String)> = sqlx::query("SELECT version, name, checksum FROM schema_migrations ORDER BY version")
+            .fetch_all(pool).await?
+            .into_iter()
+            .map(|r| (r.get(0), r.get(1), r.get(2)))
+            .collect();
+        let latest = MIGRATIONS.last().map(|m| m.0).unwrap_or(0);
+        for (version, name, stored) in &applied {
+            let Some((_, _, sql)) = MIGRATIONS.iter().find(|m| m.0 == *version) else {
+                bail!("Database has migration {version} ({name}) but this build only knows up to {latest}; it was created by a newer version of the app");
+            };
+            let expected = checksum(sql);
+            if *stored != expected {
+                bail!("Checksum mismatch for migration {version} ({name}): database has {stored}, this build has {expected}. \
+                       Applied migrations must not be edited; restore the file and add a new numbered migration instead");
             }
         }
+
+        let mut last = 0;
+        for &(version, name, sql) in MIGRATIONS {
+            if version <= last { bail!("Migrations out of order: {name} (version {version}) after version {last}"); }
+            last = version;
+            if applied.iter().any(|a| a.0 == version) { continue; }
+            // Whole file in one transaction: a failing statement leaves nothing half-applied
+            let mut tx = pool.begin().await?;
+            sqlx::raw_sql(sql).execute(&mut *tx).await
+                .with_context(|| format!("Migration {version} ({name}) failed"))?;
+            Self::record(&mut tx, version, name, sql).await?;
+            tx.commit().await?;
+            println!("[DB] Applied migration {name}");
+        }
         Ok(())
     }
 
+    /// DB from before versioned migrations: everything up to LEGACY_BASELINE has been
+    /// applied, so re-run those files the old idempotent way once and record them as applied.
+    async fn adopt_legacy(pool: &SqlitePool) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        for &(version, name, sql) in MIGRATIONS.iter().filter(|m| m.0 <= LEGACY_BASELINE) {
+            for statement in sql.split(';') {
+                let stmt = statement.trim();
+                if stmt.is_empty() { continue; }
+                match sqlx::query(stmt).execute(&mut *tx).await {
+                    Ok(_) => {}
+                    // Column already added by an earlier boot of the old runner
+                    Err(e) if stmt.contains("ADD COLUMN") && e.to_string().contains("duplicate column name") => {}
+                    Err(e) => return Err(anyhow::Error::new(e).context(format!("Adopting legacy migration {name} failed")))
+                }
+            }
+            Self::record(&mut tx, version, name, sql).await?;
+        }
+        tx.commit().await?;
+        println!("[DB] Adopted pre-versioning database at migration {LEGACY_BASELINE}");
+        Ok(())
+    }
+
+    async fn record(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, version: i64, name: &str, sql: &str) -> Result<()> {
+        sqlx::query("INSERT INTO schema_migrations(version, name, checksum, applied_at) VALUES (?,?,?,?)")
+            .bind(version)
+            .bind(name)
+            .bind(checksum(sql))
+            .bind(chrono::Utc::now().timestamp_millis())
+            .execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    async fn table_exists(pool: &SqlitePool, name: &str) -> Result<bool> {
+        let n: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?")
+            .bind(name)
+            .fetch_one(pool).await?;
+        Ok(n > 0)
+    }
+
     pub fn pool(&self) -> &SqlitePool { &self.pool }
 }
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+/// rename, falling back to copy + delete when the app data dir is on another volume
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() { return Ok(()); }
+    std::fs::copy(from, to)?;
+    std::fs::remove_file(from)
+}
+
+/// SHA-256 of the migration text; CRLF normalised so a Windows checkout hashes the same.
+fn checksum(sql: &str) -> String {
+    hex::encode(Sha256::digest(sql.replace("\r\n", "\n").as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn legacy_db_gets_migrations_after_001() {
+        let path = std::env::temp_dir().join(format!("p2p-db-{}-legacy.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // What the old runner left behind: 001 only, no schema_migrations
+        let pool = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&path).create_if_missing(true)).await.unwrap();
+        sqlx::raw_sql(MIGRATIONS[0].2).execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let db = Db::init(&path).await.unwrap();
+        let versions: Vec<i64> = sqlx::query_scalar("SELECT version FROM schema_migrations ORDER BY version")
+            .fetch_all(db.pool()).await.unwrap();
+        assert_eq!(versions, MIGRATIONS.iter().map(|m| m.0).collect::<Vec<_>>());
+        // 005 added the account dimension
+        assert!(Db::table_exists(db.pool(), "account_nicknames").await.unwrap());
+    }
+}
@@ -1,91 +1,567 @@
-﻿use std::sync::Arc;
-use tokio::sync::Mutex;
-use tauri::State;
+mod crypto;
+mod db;
+mod api;
+mod orders;
+mod ws;
+mod settings;
 
-// Trạng thái chia sẻ để lưu tối đa 200 message WebSocket (string)
-#[derive(Clone, Debug)]
-struct WsState {
-    buffer: Arc<Mutex<Vec<String>>>
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+use tauri::async_runtime::JoinHandle;
+
+use crypto::vault::{Vault, VaultStatus};
+use api::clients::ClientPool;
+use api::credentials::{CredentialProfile, CredentialsRepo};
+use api::error::ApiError;
+use api::sync_engine::ProgressFn;
+use api::sync_state::SyncWindow;
+use orders::changes::{self as order_changes, OrdersChanged};
+use orders::repo::{AccountTotals, OrderRow, OrderTimeline, RetentionPolicy};
+use orders::store::{MyNickname, OrderStore, OrderView};
+use ws::{CommandReply, WsState};
+use ws::session::{BridgeStatus, SessionEvent};
+use ws::auth::PairingRepo;
+use ws::protocol::ExtensionCommand;
+use settings::{Settings, SettingsStore, SETTINGS_FILE};
+
+// JSON Schemas of the extension bridge protocol, for the ws_schema bin
+pub use ws::protocol::schemas as ws_protocol_schemas;
+// Sync stack, for the mock_binance integration tests under tests/
+pub use api::c2c_api_client::C2CApiClient;
+pub use api::sync_engine::{SyncEngine, SyncProgress, MAX_WINDOW_MS};
+pub use api::sync_state::SyncStateRepo;
+pub use crypto::CryptoCtx;
+pub use db::Db;
+pub use orders::repo::OrderRepo;
+
+const RECV_WINDOW_MS: u64 = 10_000;
+// Override Binance host, e.g. http://127.0.0.1:9900 for the mock_binance dev server
+const API_BASE_URL_ENV: &str = "P2P_API_BASE_URL";
+// Xoá payment detail hết hạn retention
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+// App context: DB + API client (sync) + WS extension capture trong cùng một core
+struct AppCtx {
+    db: Arc<Db>,
+    crypto: CryptoCtx,
+    order_repo: Arc<OrderRepo>,
+    creds_repo: Arc<CredentialsRepo>,
+    vault: Vault,
+    sync_state: Arc<SyncStateRepo>,
+    // One API client per credential profile (merchant account)
+    clients: Arc<ClientPool>,
+    // Serialize manual and background syncs
+    sync_lock: Arc<tokio::sync::Mutex<()>>,
+    // settings.toml; fallback days are read from here on every run
+    settings: Arc<SettingsStore>,
+    // sync.interval_secs, pushed by apply_settings so the background loop restarts its wait
+    sync_interval: watch::Sender<u64>,
+    // WS pairing token the extension must present
+    pairing: PairingRepo,
+    ws: WsState,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl AppCtx {
+    async fn init(settings: SettingsStore, db_path: &Path) -> anyhow::Result<Self> {
+        let db = Arc::new(Db::init(db_path).await?);
+        // Locked until the operator enters the master password (unlock_vault)
+        let crypto = CryptoCtx::locked();
+        let vault = Vault::new(db.pool().clone(), crypto.clone());
+        let creds_repo = Arc::new(CredentialsRepo::new(db.pool().clone(), crypto.clone()));
+        let order_repo = Arc::new(OrderRepo::new(db.pool().clone(), crypto.clone()));
+        let sync_state = Arc::new(SyncStateRepo::new(db.pool().clone()));
+        let clients = Arc::new(ClientPool::new(creds_repo.clone(), new_api_client));
+
+        let store = Arc::new(OrderStore::with_repo(order_repo.clone()));
+        let restored = store.rehydrate().await?;
+        println!("[DB] Restored {restored} extension-captured orders");
+
+        let current = settings.get();
+        order_repo.set_retention(current.retention);
+        let pairing = PairingRepo::new(db.pool().clone());
+        let token = pairing.token().await?;
+        let ws = WsState::new(store, current.ingest.dedup_window_ms, current.ws.buffer_size, token, current.ws.allowed_origins);
+        Ok(Self {
+            db, crypto, order_repo, creds_repo, vault, sync_state, clients,
+            sync_lock: Arc::new(tokio::sync::Mutex::new(())),
+            sync_interval: watch::Sender::new(current.sync.interval_secs),
+            settings: Arc::new(settings),
+            pairing,
+            ws,
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn start_ws(&self, app: AppHandle) {
+        let ws_state = self.ws.clone();
+        let addr = self.settings.get().ws.addr;
+        // Webview refreshes bridge status on every connect / pair / disconnect
+        let on_session = Arc::new(move |e: &SessionEvent| { let _ = app.emit("bridge_session", e.clone()); });
+        let handle = tauri::async_runtime::spawn(async move {
+            if let Err(e) = ws::start_ws_server(&addr, ws_state, on_session).await {
+                eprintln!("[WS] Server crashed: {e:?}");
+            }
+        });
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    /// Forward order changes to the webview as `orders_changed`, coalesced so a burst
+    /// of captures / a sync produces a bounded number of events
+    fn start_order_events(&self, app: AppHandle) {
+        let repo = self.order_repo.clone();
+        let store = self.ws.ingestor.store().clone();
+        let rx = repo.changes().subscribe();
+        let handle = tauri::async_runtime::spawn(order_changes::coalesce(rx, move |batch| {
+            let (app, repo, store) = (app.clone(), repo.clone(), store.clone());
+            async move {
+                let payload = OrdersChanged::resolve(batch, &repo, &store).await;
+                if let Err(e) = app.emit("orders_changed", payload) {
+                    eprintln!("[EVENTS] Cannot emit orders_changed: {e}");
+                }
+            }
+        }));
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    fn start_background_sync(&self) {
+        let crypto = self.crypto.clone();
+        let clients = self.clients.clone();
+        let repo = self.order_repo.clone();
+        let store = self.ws.ingestor.store().clone();
+        let state = self.sync_state.clone();
+        let lock = self.sync_lock.clone();
+        let settings = self.settings.clone();
+        let mut interval = self.sync_interval.subscribe();
+        let handle = tauri::async_runtime::spawn(async move {
+            loop {
+                // 0 = off: only a settings change wakes the loop
+                let wait = match *interval.borrow_and_update() {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs))
+                };
+                let sleep = async {
+                    match wait {
+                        Some(d) => tokio::time::sleep(d).await,
+                        None => std::future::pending().await
+                    }
+                };
+                tokio::select! {
+                    changed = interval.changed() => {
+                        if changed.is_err() { break; }
+                        continue;
+                    }
+                    _ = sleep => {}
+                }
+                if !crypto.is_unlocked() { continue; }
+                let guard = lock.lock().await;
+                let fallback_days = settings.get().sync.fallback_days;
+                let res = sync_all_accounts(&clients, &repo, &store, &state, fallback_days, None).await;
+                drop(guard);
+                let results = match res {
+                    Ok(r) => r,
+                    Err(e) => { eprintln!("[SYNC] Background sync skipped: {e:#}"); continue; }
+                };
+                let mut backoff = None;
+                for (account, res) in results {
+                    match res {
+                        Ok(n) => println!("[SYNC] Background sync account={account} done ({n} orders)"),
+                        Err(e) => {
+                            eprintln!("[SYNC] Background sync account={account} failed: {e:#}");
+                            match e.downcast_ref::<ApiError>() {
+                                Some(ApiError::Auth(_)) => eprintln!("[SYNC] Check API credentials of account {account}; background sync will keep retrying"),
+                                // Ban / rate limit longer than the client waits: back off here
+                                Some(err) => backoff = backoff.max(err.retry_after()),
+                                None => {}
+                            }
+                        }
+                    }
+                }
+                if let Some(d) = backoff { tokio::time::sleep(d).await; }
+            }
+        });
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    fn start_retention_purge(&self) {
+        let repo = self.order_repo.clone();
+        let store = self.ws.ingestor.store().clone();
+        let handle = tauri::async_runtime::spawn(async move {
+            loop {
+                let now = chrono::Utc::now().timestamp_millis();
+                match repo.purge_expired_details(now).await {
+                    Ok(purged) if !purged.is_empty() => {
+                        store.clear_payment_details(&purged).await;
+                        println!("[DB] Purged payment details of {} orders", purged.len());
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("[DB] Payment detail purge failed: {e:#}")
+                }
+                tokio::time::sleep(Duration::from_secs(PURGE_INTERVAL_SECS)).await;
+            }
+        });
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    /// After unlock: encrypt legacy plaintext rows, persist details captured while
+    /// locked, then reload the decrypted details into the store
+    async fn on_unlocked(&self) -> anyhow::Result<()> {
+        let sealed = self.order_repo.seal_plaintext_details().await?;
+        if sealed > 0 { println!("[DB] Encrypted {sealed} legacy payment detail rows"); }
+        let store = self.ws.ingestor.store();
+        store.flush_payment_details(chrono::Utc::now().timestamp_millis()).await?;
+        store.rehydrate().await?;
+        Ok(())
+    }
+
+    /// Push the runtime-changeable settings into the components holding them
+    fn apply_settings(&self, settings: &Settings) {
+        self.ws.ingestor.set_dedup_window(settings.ingest.dedup_window_ms);
+        self.ws.set_buffer_size(settings.ws.buffer_size);
+        self.ws.set_allowed_origins(settings.ws.allowed_origins.clone());
+        self.order_repo.set_retention(settings.retention);
+        let secs = settings.sync.interval_secs;
+        self.sync_interval.send_if_modified(|cur| std::mem::replace(cur, secs) != secs);
+    }
+
+    /// Save + apply; returns the keys that need a restart
+    fn update_settings(&self, new: Settings) -> anyhow::Result<Vec<&'static str>> {
+        let old = self.settings.update(new.clone())?;
+        self.apply_settings(&new);
+        Ok(old.restart_required(&new))
+    }
+
+    async fn shutdown(&self) {
+        for handle in self.tasks.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+        println!("[INIT] Background tasks stopped");
+        self.db.pool().close().await;
+        println!("[DB] Pool closed");
+    }
+
+    async fn active_client(&self) -> Result<(i64, C2CApiClient), String> {
+        self.clients.active().await.map_err(|e| e.to_string())?
+            .ok_or_else(|| "Chưa cấu hình API client".to_string())
+    }
+}
+
+/// Incremental sync of every credential profile; one failing account does not stop the others
+async fn sync_all_accounts(clients: &ClientPool, repo: &OrderRepo, store: &OrderStore, state: &SyncStateRepo, fallback_days: i64, progress: Option<ProgressFn<'_>>) -> anyhow::Result<Vec<(i64, anyhow::Result<usize>)>> {
+    let mut results = Vec::new();
+    for (account, client) in clients.all().await? {
+        let mut engine = SyncEngine::new(&client, repo, state, account).with_store(store);
+        if let Some(f) = progress { engine = engine.with_progress(f); }
+        results.push((account, engine.incremental_sync(fallback_days).await));
+    }
+    Ok(results)
+}
+
+fn new_api_client(api_key: String, api_secret: String) -> C2CApiClient {
+    let client = C2CApiClient::new(api_key, api_secret).with_recv_window(RECV_WINDOW_MS);
+    match std::env::var(API_BASE_URL_ENV) {
+        Ok(base) if !base.is_empty() => client.with_base_url(&base),
+        _ => client
+    }
+}
+
+// Lưu key vào profile `label` (tạo mới hoặc thay key) và chọn profile đó
+#[tauri::command]
+async fn store_api_credentials(state: State<'_, AppCtx>, label: String, api_key: String, api_secret: String) -> Result<(), String> {
+    let id = state.creds_repo.store(&label, &api_key, &api_secret).await.map_err(|e| e.to_string())?;
+    state.clients.forget(Some(id));
+    state.creds_repo.set_active(&label).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_credential_profiles(state: State<'_, AppCtx>) -> Result<Vec<CredentialProfile>, String> {
+    state.creds_repo.list().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn select_credential_profile(state: State<'_, AppCtx>, label: String) -> Result<(), String> {
+    state.creds_repo.set_active(&label).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn rename_credential_profile(state: State<'_, AppCtx>, label: String, new_label: String) -> Result<(), String> {
+    state.creds_repo.rename(&label, &new_label).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_credential_profile(state: State<'_, AppCtx>, label: String) -> Result<(), String> {
+    let id = state.creds_repo.delete(&label).await.map_err(|e| e.to_string())?;
+    state.clients.forget(Some(id));
+    state.ws.ingestor.store().forget_account(id).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_vault_status(state: State<'_, AppCtx>) -> Result<VaultStatus, String> {
+    state.vault.status().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn setup_vault(state: State<'_, AppCtx>, password: String) -> Result<(), String> {
+    state.vault.setup(&password).await.map_err(|e| e.to_string())?;
+    state.on_unlocked().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn unlock_vault(state: State<'_, AppCtx>, password: String) -> Result<(), String> {
+    state.vault.unlock(&password).await.map_err(|e| e.to_string())?;
+    state.on_unlocked().await.map_err(|e| e.to_string())
+}
+
+// Đổi master password: re-encrypt toàn bộ secrets bằng key mới, trả về key id mới
+#[tauri::command]
+async fn rotate_master_key(state: State<'_, AppCtx>, current_password: String, new_password: String) -> Result<u32, String> {
+    let key_id = state.vault.rotate_master_key(&current_password, &new_password).await.map_err(|e| format!("{e:#}"))?;
+    state.on_unlocked().await.map_err(|e| e.to_string())?;
+    Ok(key_id)
+}
+
+#[tauri::command]
+async fn lock_vault(state: State<'_, AppCtx>) -> Result<(), String> {
+    state.vault.lock();
+    state.clients.forget(None);
+    Ok(())
+}
+
+#[tauri::command]
+async fn test_api_credentials(state: State<'_, AppCtx>) -> Result<String, String> {
+    let (_, client) = state.active_client().await?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let start = now - 5 * 60 * 1000;
+    let res = client.list_user_order_history("BUY", start, now, 1, 1).await.map_err(|e| e.to_string())?;
+    Ok(res.to_string())
 }
 
-impl WsState {
-    fn new() -> Self {
-        Self {
-            buffer: Arc::new(Mutex::new(Vec::new()))
+// Backfill một account (mặc định: profile đang chọn)
+#[tauri::command]
+async fn force_initial_sync(app: AppHandle, state: State<'_, AppCtx>, days: i64, account_id: Option<i64>) -> Result<String, String> {
+    let (account, client) = match account_id {
+        Some(id) => (id, state.clients.get(id).await.map_err(|e| e.to_string())?.ok_or_else(|| format!("Không tìm thấy account {id}"))?),
+        None => state.active_client().await?
+    };
+    let _guard = state.sync_lock.lock().await;
+    let emit = |p: &SyncProgress| { let _ = app.emit("sync_progress", p.clone()); };
+    let engine = SyncEngine::new(&client, &state.order_repo, &state.sync_state, account)
+        .with_progress(&emit).with_store(state.ws.ingestor.store());
+    engine.force_initial_sync(days).await.map_err(|e| format!("{e:#}"))?;
+    Ok("SYNC_OK".into())
+}
+
+// Incremental sync cho tất cả account
+#[tauri::command]
+async fn incremental_sync(app: AppHandle, state: State<'_, AppCtx>) -> Result<usize, String> {
+    let _guard = state.sync_lock.lock().await;
+    let emit = |p: &SyncProgress| { let _ = app.emit("sync_progress", p.clone()); };
+    let fallback_days = state.settings.get().sync.fallback_days;
+    let results = sync_all_accounts(&state.clients, &state.order_repo, state.ws.ingestor.store(), &state.sync_state, fallback_days, Some(&emit)).await
+        .map_err(|e| e.to_string())?;
+    let mut total = 0;
+    let mut errors = Vec::new();
+    for (account, res) in results {
+        match res {
+            Ok(n) => total += n,
+            Err(e) => errors.push(format!("account {account}: {e:#}"))
         }
     }
+    if errors.is_empty() { Ok(total) } else { Err(errors.join("; ")) }
+}
+
+#[tauri::command]
+async fn get_sync_state(state: State<'_, AppCtx>) -> Result<Vec<SyncWindow>, String> {
+    state.sync_state.list().await.map_err(|e| e.to_string())
+}
+
+#[derive(serde::Serialize)]
+struct SettingsUpdate {
+    settings: Settings,
+    // Keys that only take effect after restarting the app
+    restart_required: Vec<&'static str>,
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppCtx>) -> Result<Settings, String> {
+    Ok(state.settings.get())
+}
+
+// Validate + lưu settings.toml, áp dụng ngay phần đổi được lúc chạy
+#[tauri::command]
+async fn update_settings(state: State<'_, AppCtx>, settings: Settings) -> Result<SettingsUpdate, String> {
+    let restart_required = state.update_settings(settings).map_err(|e| format!("{e:#}"))?;
+    Ok(SettingsUpdate { settings: state.settings.get(), restart_required })
+}
+
+// Đọc lại settings.toml sau khi sửa tay
+#[tauri::command]
+async fn reload_settings(state: State<'_, AppCtx>) -> Result<SettingsUpdate, String> {
+    let (old, new) = state.settings.reload().map_err(|e| format!("{e:#}"))?;
+    state.apply_settings(&new);
+    Ok(SettingsUpdate { restart_required: old.restart_required(&new), settings: new })
+}
+
+// Đổi chu kỳ sync nền (giây, 0 = tắt)
+#[tauri::command]
+async fn set_sync_interval(state: State<'_, AppCtx>, secs: u64) -> Result<(), String> {
+    let mut settings = state.settings.get();
+    settings.sync.interval_secs = secs;
+    state.update_settings(settings).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Số ngày giữ payment detail sau khi order hoàn thành / bị hủy
+#[tauri::command]
+async fn set_retention_policy(state: State<'_, AppCtx>, completed_days: i64, cancelled_days: i64) -> Result<(), String> {
+    let mut settings = state.settings.get();
+    settings.retention = RetentionPolicy { completed_days, cancelled_days };
+    state.update_settings(settings).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_orders_from_db(state: State<'_, AppCtx>, limit: i64, account_id: Option<i64>) -> Result<Vec<OrderRow>, String> {
+    state.order_repo.list_orders(limit, account_id).await.map_err(|e| e.to_string())
 }
 
-// Command để frontend gọi
 #[tauri::command]
-async fn get_raw_messages(state: State<'_, WsState>) -> Result<Vec<String>, String> {
-    let buf = state.buffer.lock().await;
+async fn get_account_totals(state: State<'_, AppCtx>) -> Result<Vec<AccountTotals>, String> {
+    state.order_repo.account_totals().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_my_nickname(state: State<'_, AppCtx>) -> Result<MyNickname, String> {
+    Ok(state.ws.ingestor.store().my_nickname().await)
+}
+
+// Override nickname Binance của account `label` (mặc định: profile đang chọn)
+#[tauri::command]
+async fn set_my_nickname(state: State<'_, AppCtx>, nickname: String, label: Option<String>) -> Result<(), String> {
+    let nickname = nickname.trim().to_string();
+    if nickname.is_empty() { return Err("Nickname không được để trống".into()); }
+    let account = match label {
+        Some(label) => state.creds_repo.profile_id(&label).await.map_err(|e| e.to_string())?,
+        None => state.creds_repo.active_id().await.map_err(|e| e.to_string())?
+            .ok_or_else(|| "Chưa có API profile nào".to_string())?
+    };
+    state.ws.ingestor.store().override_my_nickname(nickname, account, chrono::Utc::now().timestamp_millis()).await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_order_timeline(state: State<'_, AppCtx>, order_number: String) -> Result<OrderTimeline, String> {
+    state.order_repo.timeline(&order_number).await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Không tìm thấy order {order_number}"))
+}
+
+#[tauri::command]
+async fn get_raw_messages(state: State<'_, AppCtx>) -> Result<Vec<String>, String> {
+    let buf = state.ws.buffer.lock().await;
     let len = buf.len();
     Ok(buf.iter().skip(len.saturating_sub(10)).cloned().collect())
 }
 
+// Token để pair extension: dán vào popup của extension
+#[tauri::command]
+async fn get_pairing_token(state: State<'_, AppCtx>) -> Result<String, String> {
+    state.pairing.token().await.map_err(|e| e.to_string())
+}
+
+// Token mới; extension đang kết nối bằng token cũ bị ngắt và phải pair lại
+#[tauri::command]
+async fn regenerate_pairing_token(state: State<'_, AppCtx>) -> Result<String, String> {
+    let token = state.pairing.rotate().await.map_err(|e| e.to_string())?;
+    state.ws.set_token(token.clone());
+    println!("[WS] Pairing token regenerated");
+    Ok(token)
+}
+
+// Extension nào đang kết nối, capture gần nhất lúc nào
+#[tauri::command]
+async fn get_bridge_status(state: State<'_, AppCtx>) -> Result<BridgeStatus, String> {
+    Ok(state.ws.status().await)
+}
+
+// Gửi lệnh tới extension (vd. fetch lại order detail); mỗi kết nối trả một reply
+#[tauri::command]
+async fn send_extension_command(state: State<'_, AppCtx>, command: ExtensionCommand) -> Result<Vec<CommandReply>, String> {
+    state.ws.send_command(command).await.map_err(|e| e.to_string())
+}
+
+// Danh sách order đã parse từ extension capture (mới nhất trước)
+#[tauri::command]
+async fn list_orders(state: State<'_, AppCtx>, account_id: Option<i64>) -> Result<Vec<OrderView>, String> {
+    let mut views = state.ws.ingestor.store().list(account_id).await;
+    views.sort_by_key(|v| std::cmp::Reverse(v.last_update_ts));
+    Ok(views)
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let ws_state = WsState::new();
-
-    // Spawn WebSocket server trong setup
-    let ws_state_for_task = ws_state.clone();
-    tauri::Builder::default()
-        .setup(move |_app| {
-            // Spawn task async using Tauri's async runtime
-            tauri::async_runtime::spawn(async move {
-                if let Err(e) = start_ws_server("127.0.0.1:8123", ws_state_for_task).await {
-                    eprintln!("[WS] Server crashed: {e:?}");
-                }
-            });
-            println!("[INIT] App started. WS listening on ws://127.0.0.1:8123");
+    let app = tauri::Builder::default()
+        .setup(|app| {
+            let settings_path = app.path().app_config_dir()?.join(SETTINGS_FILE);
+            let settings = SettingsStore::load(&settings_path)
+                .inspect_err(|e| eprintln!("[SETTINGS] {e:#}"))?;
+            let db_path = db::resolve_path(&app.path().app_data_dir()?, settings.get().db.path.as_deref());
+            let ctx = tauri::async_runtime::block_on(AppCtx::init(settings, &db_path))?;
+            ctx.start_order_events(app.handle().clone());
+            ctx.start_ws(app.handle().clone());
+            ctx.start_background_sync();
+            ctx.start_retention_purge();
+            println!("[INIT] App started. DB={}, settings={}, WS listening on ws://{}",
+                db_path.display(), ctx.settings.path().display(), ctx.settings.get().ws.addr);
+            app.manage(ctx);
             Ok(())
         })
-        .manage(ws_state)
-        .invoke_handler(tauri::generate_handler![get_raw_messages])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
-
-// ---------------- WebSocket Server ----------------
-use tokio::net::TcpListener;
-use tokio_tungstenite::accept_async;
-use futures::StreamExt;
-use anyhow::Result;
-
-async fn start_ws_server(addr: &str, shared: WsState) -> Result<()> {
-    println!("[WS] Binding {addr}");
-    let listener = TcpListener::bind(addr).await?;
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let peer_state = shared.clone();
-        tauri::async_runtime::spawn(async move {
-            let ws_stream = match accept_async(stream).await {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("[WS] Handshake error: {e}");
-                    return;
-                }
-            };
-            println!("[WS] Client connected");
-            let (mut _write, mut read) = ws_stream.split();
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(m) if m.is_text() => {
-                        let txt = m.into_text().unwrap();
-                        let mut g = peer_state.buffer.lock().await;
-                        g.push(txt);
-                        if g.len() > 200 { g.drain(0..100); }
-                        println!("[WS] Received (total stored: {})", g.len());
-                    }
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!("[WS] Read error: {e}");
-                        break;
-                    }
-                }
+        .invoke_handler(tauri::generate_handler![
+            get_vault_status,
+            setup_vault,
+            unlock_vault,
+            rotate_master_key,
+            lock_vault,
+            store_api_credentials,
+            list_credential_profiles,
+            select_credential_profile,
+            rename_credential_profile,
+            delete_credential_profile,
+            test_api_credentials,
+            force_initial_sync,
+            incremental_sync,
+            set_sync_interval,
+            set_retention_policy,
+            get_settings,
+            update_settings,
+            reload_settings,
+            get_sync_state,
+            list_orders_from_db,
+            get_account_totals,
+            get_my_nickname,
+            set_my_nickname,
+            get_order_timeline,
+            get_raw_messages,
+            send_extension_command,
+            get_bridge_status,
+            get_pairing_token,
+            regenerate_pairing_token,
+            list_orders
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application");
+
+    app.run(|handle, event| {
+        if let RunEvent::Exit = event {
+            if let Some(ctx) = handle.try_state::<AppCtx>() {
+                tauri::async_runtime::block_on(ctx.shutdown());
             }
-            println!("[WS] Client disconnected");
-        });
-    }
+        }
+    });
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::repo::{OrderRepo, OrderRow};
+use super::status::OrderStage;
+use super::store::{OrderStore, OrderView};
+
+/// Changes within this window after the first one go out as one batch
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(250);
+/// A bigger burst (initial sync, long order list) is sent as `reload` instead of per order
+pub const MAX_BATCH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrderChangeKind {
+    Created { stage: String },
+    StageChanged { from: String, to: String },
+    PaymentDetailsCaptured,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderChange {
+    pub order_number: String,
+    pub kind: OrderChangeKind,
+}
+
+impl OrderChange {
+    /// Created / StageChanged from the stage stored before a write; None if unchanged
+    pub fn stage(order_number: &str, prev: Option<OrderStage>, next: OrderStage) -> Option<Self> {
+        let kind = match prev {
+            None => OrderChangeKind::Created { stage: next.label() },
+            Some(p) if p != next => OrderChangeKind::StageChanged { from: p.label(), to: next.label() },
+            Some(_) => return None
+        };
+        Some(Self { order_number: order_number.to_string(), kind })
+    }
+}
+
+/// Where OrderRepo and OrderStore report changes. One consumer; no-op until subscribed.
+#[derive(Clone, Default)]
+pub struct ChangeFeed {
+    tx: Arc<RwLock<Option<mpsc::UnboundedSender<OrderChange>>>>
+}
+
+impl ChangeFeed {
+    /// Replaces any previous subscriber
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<OrderChange> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.tx.write().unwrap() = Some(tx);
+        rx
+    }
+
+    pub fn emit(&self, change: OrderChange) {
+        if let Some(tx) = &*self.tx.read().unwrap() {
+            let _ = tx.send(change);
+        }
+    }
+}
+
+/// Changes of one window, merged per order (first-seen order kept)
+#[derive(Debug, Default)]
+pub struct ChangeBatch {
+    pub orders: Vec<(String, Vec<OrderChangeKind>)>,
+    /// More than MAX_BATCH orders changed: `orders` is empty, reload everything
+    pub overflow: bool,
+}
+
+/// Drain `rx` into batches: one `flush` per COALESCE_WINDOW at most, however many
+/// captures arrive. Returns when every sender is gone.
+pub async fn coalesce<F, Fut>(mut rx: mpsc::UnboundedReceiver<OrderChange>, mut flush: F)
+where F: FnMut(ChangeBatch) -> Fut, Fut: Future<Output = ()> {
+    while let Some(first) = rx.recv().await {
+        let mut batch = ChangeBatch::default();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut add = |c: OrderChange| {
+            if batch.overflow { return; }
+            let i = *index.entry(c.order_number.clone()).or_insert_with(|| {
+                batch.orders.push((c.order_number, Vec::new()));
+                batch.orders.len() - 1
+            });
+            let kinds = &mut batch.orders[i].1;
+            if !kinds.contains(&c.kind) { kinds.push(c.kind); }
+            if batch.orders.len() > MAX_BATCH {
+                batch.orders.clear();
+                batch.overflow = true;
+            }
+        };
+        add(first);
+        let deadline = tokio::time::Instant::now() + COALESCE_WINDOW;
+        let mut closed = false;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(c)) => add(c),
+                Ok(None) => { closed = true; break; }
+                Err(_) => break
+            }
+        }
+        flush(batch).await;
+        if closed { break; }
+    }
+}
+
+/// Payload of the `orders_changed` webview event
+#[derive(Debug, Clone, Serialize)]
+pub struct OrdersChanged {
+    /// Too many changes to list: reload the tables
+    pub reload: bool,
+    pub orders: Vec<OrderUpdate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderUpdate {
+    pub order_number: String,
+    pub changes: Vec<OrderChangeKind>,
+    /// Current DB row (Orders table)
+    pub row: Option<OrderRow>,
+    /// Current extension view, if the extension has seen the order
+    pub view: Option<OrderView>,
+}
+
+impl OrdersChanged {
+    /// Attach the current row / view of every changed order
+    pub async fn resolve(batch: ChangeBatch, repo: &OrderRepo, store: &OrderStore) -> Self {
+        let mut orders = Vec::with_capacity(batch.orders.len());
+        for (order_number, changes) in batch.orders {
+            let row = match repo.get_order(&order_number).await {
+                Ok(row) => row,
+                Err(e) => { eprintln!("[EVENTS] Cannot load order {order_number}: {e}"); None }
+            };
+            let view = store.view(&order_number).await;
+            orders.push(OrderUpdate { order_number, changes, row, view });
+        }
+        Self { reload: batch.overflow, orders }
+    }
+}
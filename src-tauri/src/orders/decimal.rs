@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, bail};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use sqlx::{Decode, Encode, Sqlite, Type};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+
+// Binance trả tối đa 8 chữ số thập phân; 28 để dư cho tích price * amount
+const MAX_SCALE: u32 = 28;
+
+/// Exact decimal: `mantissa / 10^scale`. The scale is kept as received so values
+/// round-trip to the same text ("25500.00" stays "25500.00") and tells how precise
+/// the number is. Comparison is numeric (1.0 == 1.00).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { mantissa: 0, scale: 0 };
+
+    /// String ("123.45") or plain JSON number; anything else / empty -> None
+    pub fn from_json(v: Option<&Value>) -> Option<Self> {
+        match v? {
+            Value::String(s) => s.parse().ok(),
+            Value::Number(n) => n.to_string().parse().ok(),
+            _ => None
+        }
+    }
+
+    pub fn abs(self) -> Self { Self { mantissa: self.mantissa.abs(), scale: self.scale } }
+
+    /// One unit in the last place (0.01 for "12.34")
+    pub fn ulp(&self) -> Self { Self { mantissa: 1, scale: self.scale } }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.rescale(scale)?, other.rescale(scale)?);
+        Some(Self { mantissa: a.checked_add(b)?, scale })
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.checked_add(Self { mantissa: other.mantissa.checked_neg()?, scale: other.scale })
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let scale = self.scale + other.scale;
+        if scale > MAX_SCALE { return None; }
+        Some(Self { mantissa: self.mantissa.checked_mul(other.mantissa)?, scale })
+    }
+
+    /// Mantissa at a larger scale
+    fn rescale(&self, scale: u32) -> Option<i128> {
+        10i128.checked_pow(scale - self.scale).and_then(|f| self.mantissa.checked_mul(f))
+    }
+
+    /// (integer part, fractional part at `scale` digits); `scale` >= self.scale, <= MAX_SCALE
+    fn split(&self, scale: u32) -> (i128, i128) {
+        let unit = 10i128.pow(self.scale);
+        (self.mantissa / unit, (self.mantissa % unit) * 10i128.pow(scale - self.scale))
+    }
+}
+
+/// `price * amount ≈ total`: Binance rounds each of the three to its own precision, so
+/// allow one unit in the last place of each (propagated through the product).
+/// None when the check itself overflows.
+pub fn amounts_consistent(price: Decimal, amount: Decimal, total: Decimal) -> Option<bool> {
+    let diff = price.checked_mul(amount)?.checked_sub(total)?.abs();
+    let tolerance = price.abs().checked_mul(amount.ulp())?
+        .checked_add(amount.abs().checked_mul(price.ulp())?)?
+        .checked_add(total.ulp())?;
+    Some(diff <= tolerance)
+}
+
+/// True when all three are known and disagree
+pub fn amounts_mismatch(price: Option<Decimal>, amount: Option<Decimal>, total: Option<Decimal>) -> bool {
+    match (price, amount, total) {
+        (Some(p), Some(a), Some(t)) => amounts_consistent(p, a, t) == Some(false),
+        _ => false
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let t = s.trim();
+        // serde_json prints small / large floats in exponent form ("1e-8")
+        let (t, exp) = match t.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i64>().map_err(|_| anyhow!("Invalid decimal '{s}'"))?),
+            None => (t, 0)
+        };
+        let (negative, digits) = match t.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, t.strip_prefix('+').unwrap_or(t))
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty() && frac.is_empty() { bail!("Invalid decimal '{s}'"); }
+        if !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) { bail!("Invalid decimal '{s}'"); }
+        let scale = (frac.len() as i64).saturating_sub(exp);
+        if scale > MAX_SCALE as i64 { bail!("Decimal '{s}' has more than {MAX_SCALE} fractional digits"); }
+        let out_of_range = || anyhow!("Decimal '{s}' out of range");
+        let mut mantissa: i128 = 0;
+        for b in int.bytes().chain(frac.bytes()) {
+            mantissa = mantissa.checked_mul(10)
+                .and_then(|m| m.checked_add((b - b'0') as i128))
+                .ok_or_else(out_of_range)?;
+        }
+        // Positive exponent beyond the fraction digits ("1.5e3"): scale up to an integer
+        if scale < 0 {
+            mantissa = u32::try_from(-scale).ok()
+                .and_then(|e| 10i128.checked_pow(e))
+                .and_then(|f| mantissa.checked_mul(f))
+                .ok_or_else(out_of_range)?;
+        }
+        Ok(Self { mantissa: if negative { -mantissa } else { mantissa }, scale: scale.max(0) as u32 })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if scale == 0 { return write!(f, "{sign}{digits}"); }
+        let padded = format!("{digits:0>width$}", width = scale + 1);
+        let (int, frac) = padded.split_at(padded.len() - scale);
+        write!(f, "{sign}{int}.{frac}")
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Split instead of rescaling the whole mantissa: cannot overflow
+        let scale = self.scale.max(other.scale);
+        self.split(scale).cmp(&other.split(scale))
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Decimal {}
+
+// Ra webview dạng string để JS không làm tròn qua f64
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+// SQLite: TEXT column holding the exact string
+impl Type<Sqlite> for Decimal {
+    fn type_info() -> SqliteTypeInfo { <String as Type<Sqlite>>::type_info() }
+    fn compatible(ty: &SqliteTypeInfo) -> bool { <String as Type<Sqlite>>::compatible(ty) }
+}
+
+impl<'q> Encode<'q, Sqlite> for Decimal {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> IsNull {
+        <String as Encode<'q, Sqlite>>::encode(self.to_string(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for Decimal {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let text = <&str as Decode<'r, Sqlite>>::decode(value)?;
+        Ok(text.parse::<Decimal>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn d(s: &str) -> Decimal { s.parse().unwrap() }
+
+    #[test]
+    fn parses_exponent_sign_and_padding() {
+        assert_eq!(d("1e-8").to_string(), "0.00000001");
+        assert_eq!(d("2.5E2").to_string(), "250");
+        assert_eq!(d("-0.10").to_string(), "-0.10");
+        assert_eq!(d("+3").to_string(), "3");
+        assert_eq!(d("007.50").to_string(), "7.50");
+        assert_eq!(d(".5").to_string(), "0.5");
+        assert_eq!(d(" 12.000 ").to_string(), "12.000");
+        assert_eq!(Decimal::from_json(Some(&json!(0.00000001))), Some(d("0.00000001")));
+        assert_eq!(Decimal::from_json(Some(&json!(25500))), Some(d("25500")));
+        assert_eq!(Decimal::from_json(Some(&json!(""))), None);
+        assert_eq!(Decimal::from_json(Some(&Value::Null)), None);
+    }
+
+    #[test]
+    fn rejects_garbage_and_overflow() {
+        for bad in ["", " ", ".", "-", "abc", "1.2.3", "1,5", "--1", "+-1", "1e", "e5", "1e-x", "0x10", "NaN"] {
+            assert!(bad.parse::<Decimal>().is_err(), "{bad:?}");
+        }
+        assert_eq!(d(&i128::MAX.to_string()).to_string(), i128::MAX.to_string());
+        assert!("170141183460469231731687303715884105728".parse::<Decimal>().is_err());
+        assert!("1e39".parse::<Decimal>().is_err());
+        assert!(format!("0.{}", "1".repeat(29)).parse::<Decimal>().is_err());
+        assert!("1e-29".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["0", "25500.00", "-0.10", "0.00000001", "123456789.123456789", "-42", "0.0000000000000000000000000001"] {
+            assert_eq!(d(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn orders_numerically_across_scales() {
+        assert_eq!(d("1.50"), d("1.5"));
+        assert_eq!(d("0"), d("-0.000"));
+        assert!(d("1.5") < d("1.51"));
+        assert!(d("-1.5") < d("-1.49"));
+        assert!(d("-0.1") < Decimal::ZERO);
+        // would overflow if both were rescaled to 28 digits
+        assert!(d(&i128::MAX.to_string()) > d("0.0000000000000000000000000001"));
+        assert!(d(&i128::MIN.to_string().replace("728", "727")) < d("-0.0000000000000000000000000001"));
+    }
+
+    #[test]
+    fn checked_arithmetic_at_limits() {
+        let tiny = d("0.00000000000001");
+        assert_eq!(tiny.checked_mul(tiny), Some(d("0.0000000000000000000000000001")));
+        assert_eq!(tiny.checked_mul(d("0.000000000000001")), None);
+        assert_eq!(d(&i128::MAX.to_string()).checked_mul(d("2")), None);
+        assert_eq!(d("1.5").checked_add(d("2.25")), Some(d("3.75")));
+        assert_eq!(d("100000000000000000000").checked_add(d("0.0000000000000000000000000001")), None);
+        assert_eq!(d(&i128::MAX.to_string()).checked_add(d("1")), None);
+        assert_eq!(d("1").checked_sub(d("0.01")), Some(d("0.99")));
+    }
+
+    #[test]
+    fn amount_tolerance() {
+        assert_eq!(amounts_consistent(d("25500.00"), d("10.00"), d("255000")), Some(true));
+        // 0.33 * 3.000 = 0.99; tolerance 0.33*0.001 + 3*0.01 + 0.01 = 0.04033
+        assert_eq!(amounts_consistent(d("0.33"), d("3.000"), d("1.03")), Some(true));
+        assert_eq!(amounts_consistent(d("0.33"), d("3.000"), d("1.04")), Some(false));
+        assert!(amounts_mismatch(Some(d("0.33")), Some(d("3.000")), Some(d("1.04"))));
+        assert!(!amounts_mismatch(Some(d("0.33")), None, Some(d("1.04"))));
+        // check overflows -> not reported as a mismatch
+        let fine = d("0.00000000000000000001");
+        assert_eq!(amounts_consistent(fine, fine, d("1")), None);
+        assert!(!amounts_mismatch(Some(fine), Some(fine), Some(d("1"))));
+    }
+}
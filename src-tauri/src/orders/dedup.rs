@@ -22,11 +22,16 @@ impl Deduper {
         }
     }
 
-    pub fn make_fp(url: &str, status: i64, body_len: usize) -> String {
+    pub fn set_window(&mut self, window_ms: u64) {
+        self.window = Duration::from_millis(window_ms);
+    }
+
+    // Hash the whole body: same-length bodies can differ (orderStatus 1 -> 2)
+    pub fn make_fp(url: &str, status: i64, body: &str) -> String {
         let mut h = Sha1::new();
         h.update(url.as_bytes());
         h.update(status.to_le_bytes());
-        h.update(body_len.to_le_bytes());
+        h.update(body.as_bytes());
         format!("{:x}", h.finalize())
     }
 
@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+use serde_json::Value;
+use anyhow::Result;
+
+use super::dedup::Deduper;
+use super::parser_list::parse_order_list;
+use super::parser_detail::parse_order_detail;
+use super::nickname::{self, NicknameSource};
+use super::store::OrderStore;
+
+/// Loại endpoint của một capture từ extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    OrderList,
+    OrderDetail,
+    // Logged-in user's own profile (never another user's public profile)
+    UserInfo,
+    Other
+}
+
+/// Classify by URL path (case-insensitive). Detail is checked first because
+/// detail URLs also contain "order".
+pub fn classify(url: &str) -> Endpoint {
+    let lower = url.to_lowercase();
+    if lower.contains("getuserorderdetail") || lower.contains("orderdetail") || lower.contains("order-detail") {
+        Endpoint::OrderDetail
+    } else if lower.contains("order-list") || lower.contains("orderlist") || lower.contains("order-history")
+        || lower.contains("listuserorderhistory") || lower.contains("listorders") {
+        Endpoint::OrderList
+    } else if lower.contains("/private/") && (lower.contains("user/base-detail") || lower.contains("user-info")
+        || lower.contains("userinfo") || lower.contains("user/profile")) {
+        Endpoint::UserInfo
+    } else {
+        Endpoint::Other
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngestOutcome {
+    List(usize),
+    Detail(String),
+    Nickname(String),
+    Duplicate,
+    Ignored
+}
+
+/// Pipeline: capture -> classify -> dedup -> parse -> OrderStore
+pub struct Ingestor {
+    store: Arc<OrderStore>,
+    deduper: Mutex<Deduper>
+}
+
+impl Ingestor {
+    pub fn new(store: Arc<OrderStore>, dedup_window_ms: u64) -> Self {
+        Self { store, deduper: Mutex::new(Deduper::new(dedup_window_ms, 500)) }
+    }
+
+    pub fn store(&self) -> &Arc<OrderStore> { &self.store }
+
+    pub fn set_dedup_window(&self, window_ms: u64) {
+        self.deduper.lock().unwrap().set_window(window_ms);
+    }
+
+    /// Ingest one captured response (`NET_CAPTURE` payload): request `url`, HTTP `status`, JSON `body`
+    pub async fn ingest_capture(&self, url: &str, status: i64, body: &Value, ts: i64) -> Result<IngestOutcome> {
+        if body.is_null() { return Ok(IngestOutcome::Ignored); }
+
+        let endpoint = classify(url);
+        if endpoint == Endpoint::Other { return Ok(IngestOutcome::Ignored); }
+
+        let fp = Deduper::make_fp(url, status, &body.to_string());
+        if !self.deduper.lock().unwrap().allow(&fp) {
+            return Ok(IngestOutcome::Duplicate);
+        }
+
+        match endpoint {
+            Endpoint::OrderList => {
+                let list = parse_order_list(body);
+                let n = list.len();
+                let mine = nickname::infer_from_orders(&list);
+                if n > 0 { self.store.upsert_summaries(list, ts).await?; }
+                if let Some(nick) = mine {
+                    self.store.observe_my_nickname(nick, NicknameSource::Pattern, ts).await?;
+                }
+                Ok(IngestOutcome::List(n))
+            }
+            Endpoint::OrderDetail => match parse_order_detail(body) {
+                Some(d) => {
+                    let order_number = d.order_number.clone();
+                    self.store.upsert_detail(d, ts).await?;
+                    Ok(IngestOutcome::Detail(order_number))
+                }
+                None => Ok(IngestOutcome::Ignored)
+            },
+            Endpoint::UserInfo => match nickname::parse_user_info(body) {
+                Some(nick) => {
+                    self.store.observe_my_nickname(nick.clone(), NicknameSource::Profile, ts).await?;
+                    Ok(IngestOutcome::Nickname(nick))
+                }
+                None => Ok(IngestOutcome::Ignored)
+            },
+            Endpoint::Other => Ok(IngestOutcome::Ignored)
+        }
+    }
+}
@@ -1,5 +1,10 @@
 pub mod status;
+pub mod decimal;
 pub mod parser_list;
 pub mod parser_detail;
 pub mod store;
-pub mod dedup;
\ No newline at end of file
+pub mod dedup;
+pub mod ingest;
+pub mod nickname;
+pub mod repo;
+pub mod changes;
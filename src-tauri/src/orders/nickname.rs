@@ -0,0 +1,72 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use super::parser_list::OrderSummaryParsed;
+
+/// Orders in one list capture needed before trusting the buyer/seller pattern
+const MIN_PATTERN_ORDERS: usize = 3;
+
+/// Where "my nickname" came from. Manual overrides are never replaced by detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum NicknameSource {
+    Manual,
+    Profile,
+    Pattern
+}
+
+impl NicknameSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NicknameSource::Manual => "manual",
+            NicknameSource::Profile => "profile",
+            NicknameSource::Pattern => "pattern"
+        }
+    }
+
+    pub fn from_db(s: &str) -> Self {
+        match s {
+            "profile" => NicknameSource::Profile,
+            "pattern" => NicknameSource::Pattern,
+            _ => NicknameSource::Manual
+        }
+    }
+}
+
+/// Nickname from a captured user-info / profile response body (`data` or the body itself)
+pub fn parse_user_info(body: &Value) -> Option<String> {
+    let data = body.get("data").filter(|d| d.is_object()).unwrap_or(body);
+    let candidates = [
+        data.get("nickName"),
+        data.get("nickname"),
+        data.get("userNickName"),
+        data.get("userDetailVo").and_then(|u| u.get("nickName")),
+        data.get("baseInfo").and_then(|u| u.get("nickName"))
+    ];
+    candidates.into_iter().flatten()
+        .filter_map(|v| v.as_str())
+        .map(str::trim)
+        .find(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+/// Private order lists report `tradeType` from the logged-in user's side, so the
+/// buyer of BUY orders and the seller of SELL orders is us. Only trusted when every
+/// order in the capture agrees.
+pub fn infer_from_orders(list: &[OrderSummaryParsed]) -> Option<String> {
+    let mut mine: Option<&str> = None;
+    let mut votes = 0;
+    for o in list {
+        let nick = match o.trade_type.as_str() {
+            "BUY" => o.buyer_nick.as_str(),
+            "SELL" => o.seller_nick.as_str(),
+            _ => continue
+        };
+        if nick.is_empty() { continue; }
+        match mine {
+            Some(m) if m != nick => return None,
+            _ => mine = Some(nick)
+        }
+        votes += 1;
+    }
+    if votes >= MIN_PATTERN_ORDERS { mine.map(str::to_string) } else { None }
+}
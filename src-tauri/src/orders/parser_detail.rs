@@ -15,14 +15,15 @@ pub struct PaymentField {
     pub field_value: Option<String>
 }
 
-pub fn parse_order_detail(root: &Value) -> Option<OrderDetailParsed> {
-    let data = root.get("data")?.get("data")?;
+/// Parse an order-detail response body (`{data: {...}}`)
+pub fn parse_order_detail(body: &Value) -> Option<OrderDetailParsed> {
+    let data = body.get("data")?;
     let order_number = data.get("orderNumber").and_then(|x| x.as_str())?.to_string();
     let order_status_raw = data.get("orderStatus").and_then(|x| x.as_i64()).unwrap_or(-1) as u8;
     let mut payment_fields = vec![];
 
     if let Some(pm_arr) = data.get("payMethods").and_then(|x| x.as_array()) {
-        if let Some(first) = pm_arr.get(0) {
+        if let Some(first) = pm_arr.first() {
             if let Some(fields) = first.get("fields").and_then(|x| x.as_array()) {
                 for f in fields {
                     payment_fields.push(PaymentField {
@@ -1,14 +1,16 @@
 use serde_json::Value;
 
+use super::decimal::Decimal;
+
 #[derive(Debug, Clone)]
 pub struct OrderSummaryParsed {
     pub order_number: String,
     pub trade_type: String,
     pub asset: String,
     pub fiat: String,
-    pub amount_asset: String,
-    pub total_fiat: String,
-    pub price: String,
+    pub amount_asset: Option<Decimal>,
+    pub total_fiat: Option<Decimal>,
+    pub price: Option<Decimal>,
     pub order_status_raw: u8,
     pub create_time_ms: i64,
     pub buyer_nick: String,
@@ -22,12 +24,10 @@ pub struct PaymentField {
     pub field_value: Option<String>
 }
 
-/// Parse order-list responses
-pub fn parse_order_list(root: &Value) -> Vec<OrderSummaryParsed> {
+/// Parse an order-list response body (`{data: [...]}`)
+pub fn parse_order_list(body: &Value) -> Vec<OrderSummaryParsed> {
     let mut out = vec![];
-    let arr = match root.get("data")
-        .and_then(|d| d.get("data"))
-        .and_then(|x| x.as_array()) {
+    let arr = match body.get("data").and_then(|x| x.as_array()) {
         Some(a) => a,
         None => return out
     };
@@ -39,9 +39,9 @@ pub fn parse_order_list(root: &Value) -> Vec<OrderSummaryParsed> {
         let trade_type = it.get("tradeType").and_then(|x| x.as_str()).unwrap_or("").to_string();
         let asset = it.get("asset").and_then(|x| x.as_str()).unwrap_or("").to_string();
         let fiat = it.get("fiat").and_then(|x| x.as_str()).unwrap_or("").to_string();
-        let amount_asset = it.get("amount").and_then(|x| x.as_str()).unwrap_or("").to_string();
-        let total_fiat = it.get("totalPrice").and_then(|x| x.as_str()).unwrap_or("").to_string();
-        let price = it.get("price").and_then(|x| x.as_str()).unwrap_or("").to_string();
+        let amount_asset = Decimal::from_json(it.get("amount"));
+        let total_fiat = Decimal::from_json(it.get("totalPrice"));
+        let price = Decimal::from_json(it.get("price"));
         let order_status_raw = it.get("orderStatus").and_then(|x| x.as_i64()).unwrap_or(-1) as u8;
         let create_time_ms = it.get("createTime").and_then(|x| x.as_i64()).unwrap_or(0);
         let buyer_nick = it.get("buyerNickname").and_then(|x| x.as_str()).unwrap_or("").to_string();
@@ -51,7 +51,7 @@ pub fn parse_order_list(root: &Value) -> Vec<OrderSummaryParsed> {
         let payment_fields = it.get("payMethods")
             .and_then(|pm| pm.as_array())
             .and_then(|pmlist| {
-                pmlist.get(0)
+                pmlist.first()
                     .and_then(|first| first.get("fields"))
                     .and_then(|f| f.as_array())
                     .map(|arrf| {
@@ -1,16 +1,35 @@
-use anyhow::Result;
-use sqlx::{SqlitePool, Row};
-use serde::Serialize;
+use anyhow::{Result, anyhow};
+use sqlx::{SqlitePool, SqliteConnection, Row};
+use sqlx::sqlite::SqliteRow;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use crate::crypto::{self, CryptoCtx};
+use super::changes::{ChangeFeed, OrderChange};
+use super::decimal::{self, Decimal};
+use super::nickname::NicknameSource;
+use super::status::OrderStage;
+use super::store::Order;
+
+/// Bit flags trong cột `orders.source_flags`
+pub const SOURCE_API: i64 = 1;
+pub const SOURCE_EXT: i64 = 2;
+
+/// Giá trị cột `order_events.source`
+pub const EVENT_SOURCE_API: &str = "api";
+pub const EVENT_SOURCE_EXT: &str = "ext";
+
+#[derive(Debug, Clone, Serialize)]
 pub struct OrderRow {
     pub order_number: String,
+    pub account_id: Option<i64>,
     pub trade_type: String,
     pub fiat: String,
     pub asset: String,
-    pub amount_asset: String,
-    pub total_fiat: String,
-    pub price: String,
+    pub amount_asset: Option<Decimal>,
+    pub total_fiat: Option<Decimal>,
+    pub price: Option<Decimal>,
+    // price * amount không khớp total_fiat
+    pub amount_mismatch: bool,
     pub status_code: i64,
     pub status_label: String,
     pub buyer_nickname: String,
@@ -19,61 +38,508 @@ pub struct OrderRow {
     pub last_api_sync_ts: i64
 }
 
-pub struct OrderRepo { pool: SqlitePool }
-impl OrderRepo { pub fn new(pool: SqlitePool) -> Self { Self { pool } } }
+/// Per account + fiat totals; amounts only count completed orders
+#[derive(Debug, Serialize)]
+pub struct AccountTotals {
+    pub account_id: Option<i64>,
+    pub label: Option<String>,
+    pub fiat: String,
+    pub orders: i64,
+    pub completed: i64,
+    pub buy_fiat: Decimal,
+    pub sell_fiat: Decimal,
+    pub buy_asset: Decimal,
+    pub sell_asset: Decimal,
+    // Orders (any status) where price * amount != total_fiat
+    pub mismatched: i64
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderEvent {
+    pub from_code: Option<i64>,
+    pub to_code: i64,
+    pub to_label: String,
+    pub source: String,
+    pub observed_at: i64
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderTimeline {
+    pub order_number: String,
+    pub create_time_ms: Option<i64>,
+    pub buyer_paid_time_ms: Option<i64>,
+    pub released_time_ms: Option<i64>,
+    pub cancelled_time_ms: Option<i64>,
+    pub events: Vec<OrderEvent>
+}
+
+/// How long counterparty bank details are kept once an order is finished.
+/// Live orders (pending / paid / appealing) are never purged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub completed_days: i64,
+    pub cancelled_days: i64
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self { Self { completed_days: 7, cancelled_days: 1 } }
+}
+
+impl RetentionPolicy {
+    /// `purge_after` for a detail row of an order at `stage`, counted from `ts`
+    pub fn purge_after(&self, stage: OrderStage, ts: i64) -> Option<i64> {
+        const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+        match stage {
+            OrderStage::Completed => Some(ts + self.completed_days * DAY_MS),
+            OrderStage::Cancelled | OrderStage::CancelledBySystem => Some(ts + self.cancelled_days * DAY_MS),
+            _ => None
+        }
+    }
+}
+
+pub struct OrderRepo {
+    pool: SqlitePool,
+    // order_payment_detail columns are stored encrypted
+    crypto: CryptoCtx,
+    retention: std::sync::RwLock<RetentionPolicy>,
+    // Created / stage changed, after commit; shared with OrderStore
+    changes: ChangeFeed
+}
+
+impl OrderRepo {
+    pub fn new(pool: SqlitePool, crypto: CryptoCtx) -> Self {
+        Self { pool, crypto, retention: std::sync::RwLock::new(RetentionPolicy::default()), changes: ChangeFeed::default() }
+    }
+
+    pub fn changes(&self) -> &ChangeFeed { &self.changes }
+
+    pub fn set_retention(&self, policy: RetentionPolicy) {
+        *self.retention.write().unwrap() = policy;
+    }
+
+    fn retention(&self) -> RetentionPolicy { *self.retention.read().unwrap() }
+
+    fn seal(&self, v: &Option<String>) -> Result<Option<Vec<u8>>> {
+        v.as_ref().map(|s| self.crypto.encrypt(s.as_bytes())).transpose()
+    }
+
+    /// Decrypt a detail column; None while the vault is locked. Rows written
+    /// before field encryption are plaintext and returned as-is.
+    fn open(&self, v: Option<Vec<u8>>) -> Option<String> {
+        let v = v?;
+        if crypto::is_sealed(&v) {
+            self.crypto.decrypt(&v).ok().and_then(|p| String::from_utf8(p).ok())
+        } else {
+            String::from_utf8(v).ok()
+        }
+    }
+}
 
 impl OrderRepo {
-    pub async fn upsert_from_api(&self, order: &serde_json::Value, now: i64) -> Result<()> {
+    /// Returns the stage now stored (None: no order number)
+    pub async fn upsert_from_api(&self, order: &serde_json::Value, account_id: i64, now: i64) -> Result<Option<OrderStage>> {
         let order_number = order.get("orderNumber").and_then(|x| x.as_str()).unwrap_or("");
-        if order_number.is_empty() { return Ok(()); }
+        if order_number.is_empty() { return Ok(None); }
         let trade_type = order.get("tradeType").and_then(|x| x.as_str()).unwrap_or("");
         let asset = order.get("asset").and_then(|x| x.as_str()).unwrap_or("");
         let fiat = order.get("fiat").and_then(|x| x.as_str()).unwrap_or("");
-        let amount_asset = order.get("amount").and_then(|x| x.as_str()).unwrap_or("");
-        let total_fiat = order.get("totalPrice").and_then(|x| x.as_str()).unwrap_or("");
-        let price = order.get("price").and_then(|x| x.as_str()).unwrap_or("");
-        let status_code = order.get("orderStatus").and_then(|x| x.as_i64()).unwrap_or(-1);
+        let amount_asset = Decimal::from_json(order.get("amount"));
+        let total_fiat = Decimal::from_json(order.get("totalPrice"));
+        // sapi history uses `unitPrice`, bapi captures use `price`
+        let price = Decimal::from_json(order.get("price").or_else(|| order.get("unitPrice")));
         let create_time = order.get("createTime").and_then(|x| x.as_i64()).unwrap_or(0);
-        let buyer_nick = order.get("buyerNickname").and_then(|x| x.as_str()).unwrap_or("");
-        let seller_nick = order.get("sellerNickname").and_then(|x| x.as_str()).unwrap_or("");
-        sqlx::query(r#"INSERT INTO orders (order_number, trade_type, asset, fiat, price, amount_asset, total_fiat, order_status_code, create_time_ms, update_time_ms, buyer_nickname, seller_nickname, last_api_sync_ts, source_flags) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,1) ON CONFLICT(order_number) DO UPDATE SET trade_type=excluded.trade_type, asset=excluded.asset, fiat=excluded.fiat, price=excluded.price, amount_asset=excluded.amount_asset, total_fiat=excluded.total_fiat, order_status_code=excluded.order_status_code, update_time_ms=excluded.update_time_ms, buyer_nickname=excluded.buyer_nickname, seller_nickname=excluded.seller_nickname, last_api_sync_ts=excluded.last_api_sync_ts, source_flags = orders.source_flags | 1"#)
-            .bind(order_number).bind(trade_type).bind(asset).bind(fiat).bind(price).bind(amount_asset).bind(total_fiat).bind(status_code).bind(create_time).bind(now).bind(buyer_nick).bind(seller_nick).bind(now)
-            .execute(&self.pool).await?;
+        let mut buyer_nick = order.get("buyerNickname").and_then(|x| x.as_str()).unwrap_or("");
+        let mut seller_nick = order.get("sellerNickname").and_then(|x| x.as_str()).unwrap_or("");
+        // sapi history only has the counterparty: the seller when we BUY, the buyer when we SELL
+        if let Some(cp) = order.get("counterPartNickName").and_then(|x| x.as_str()) {
+            match trade_type {
+                "BUY" if seller_nick.is_empty() => seller_nick = cp,
+                "SELL" if buyer_nick.is_empty() => buyer_nick = cp,
+                _ => {}
+            }
+        }
+        let mut tx = self.pool.begin().await?;
+        let (prev, stage) = guard_stage(&mut tx, order_number, OrderStage::from_api_value(order.get("orderStatus"))).await?;
+        let status_code = stage.code() as i64;
+        sqlx::query(r#"INSERT INTO orders (order_number, trade_type, asset, fiat, price, amount_asset, total_fiat, order_status_code, create_time_ms, update_time_ms, buyer_nickname, seller_nickname, last_api_sync_ts, source_flags, account_id) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15) ON CONFLICT(order_number) DO UPDATE SET account_id=excluded.account_id, trade_type=COALESCE(NULLIF(excluded.trade_type,''), orders.trade_type), asset=COALESCE(NULLIF(excluded.asset,''), orders.asset), fiat=COALESCE(NULLIF(excluded.fiat,''), orders.fiat), price=COALESCE(NULLIF(excluded.price,''), orders.price), amount_asset=COALESCE(excluded.amount_asset, orders.amount_asset), total_fiat=COALESCE(excluded.total_fiat, orders.total_fiat), order_status_code=excluded.order_status_code, update_time_ms=excluded.update_time_ms, buyer_nickname=COALESCE(NULLIF(excluded.buyer_nickname,''), orders.buyer_nickname), seller_nickname=COALESCE(NULLIF(excluded.seller_nickname,''), orders.seller_nickname), last_api_sync_ts=excluded.last_api_sync_ts, source_flags = orders.source_flags | ?14"#)
+            .bind(order_number).bind(trade_type).bind(asset).bind(fiat).bind(price).bind(amount_asset).bind(total_fiat).bind(status_code).bind(create_time).bind(now).bind(buyer_nick).bind(seller_nick).bind(now).bind(SOURCE_API).bind(account_id)
+            .execute(&mut *tx).await?;
+        record_stage_change(&mut tx, order_number, prev, stage, EVENT_SOURCE_API, now).await?;
+        if prev != Some(stage) {
+            apply_retention(&mut tx, order_number, self.retention().purge_after(stage, now)).await?;
+        }
+        tx.commit().await?;
+        if let Some(change) = OrderChange::stage(order_number, prev, stage) { self.changes.emit(change); }
+        Ok(Some(stage))
+    }
+
+    /// Persist an extension-captured order (+ payment detail if present).
+    /// Empty fields from a detail-only capture never overwrite data already in the row.
+    /// Payment details are only written while the vault is unlocked (they stay in memory otherwise).
+    pub async fn upsert_from_ext(&self, o: &Order, now: i64) -> Result<()> {
+        let has_detail = (o.account_no.is_some() || o.account_name.is_some()) && self.crypto.is_unlocked();
+        let mut tx = self.pool.begin().await?;
+        let (prev, stage) = guard_stage(&mut tx, &o.order_number, o.stage).await?;
+        sqlx::query(r#"INSERT INTO orders (order_number, trade_type, asset, fiat, price, amount_asset, total_fiat, order_status_code, create_time_ms, update_time_ms, buyer_nickname, seller_nickname, last_ext_update_ts, source_flags, has_payment_detail, remark, account_id) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17) ON CONFLICT(order_number) DO UPDATE SET account_id=COALESCE(orders.account_id, excluded.account_id), trade_type=COALESCE(NULLIF(excluded.trade_type,''), orders.trade_type), asset=COALESCE(NULLIF(excluded.asset,''), orders.asset), fiat=COALESCE(NULLIF(excluded.fiat,''), orders.fiat), price=COALESCE(NULLIF(excluded.price,''), orders.price), amount_asset=COALESCE(NULLIF(excluded.amount_asset,''), orders.amount_asset), total_fiat=COALESCE(NULLIF(excluded.total_fiat,''), orders.total_fiat), order_status_code=excluded.order_status_code, create_time_ms=COALESCE(NULLIF(excluded.create_time_ms,0), orders.create_time_ms), update_time_ms=excluded.update_time_ms, buyer_nickname=COALESCE(NULLIF(excluded.buyer_nickname,''), orders.buyer_nickname), seller_nickname=COALESCE(NULLIF(excluded.seller_nickname,''), orders.seller_nickname), last_ext_update_ts=excluded.last_ext_update_ts, source_flags = orders.source_flags | ?14, has_payment_detail = MAX(orders.has_payment_detail, excluded.has_payment_detail), remark=COALESCE(excluded.remark, orders.remark)"#)
+            .bind(&o.order_number).bind(&o.trade_type).bind(&o.asset).bind(&o.fiat).bind(o.price).bind(o.amount_asset).bind(o.total_fiat)
+            .bind(stage.code() as i64).bind(o.create_time_ms).bind(now).bind(&o.buyer_nick).bind(&o.seller_nick).bind(now)
+            .bind(SOURCE_EXT).bind(has_detail as i64).bind(&o.remark).bind(o.account_id)
+            .execute(&mut *tx).await?;
+        record_stage_change(&mut tx, &o.order_number, prev, stage, EVENT_SOURCE_EXT, now).await?;
+
+        if has_detail {
+            let (name, no, qr) = (self.seal(&o.account_name)?, self.seal(&o.account_no)?, self.seal(&o.qr_code)?);
+            let (bank, sub_bank) = (self.seal(&o.bank_name)?, self.seal(&o.sub_bank)?);
+            let updated = sqlx::query(r#"UPDATE order_payment_detail SET account_name=?2, account_no=?3, bank_name=?4, sub_bank=?5, qr_code_url=?6, captured_at=?7 WHERE order_number=?1"#)
+                .bind(&o.order_number).bind(&name).bind(&no).bind(&bank).bind(&sub_bank).bind(&qr).bind(now)
+                .execute(&mut *tx).await?;
+            if updated.rows_affected() == 0 {
+                sqlx::query(r#"INSERT INTO order_payment_detail (order_number, account_name, account_no, bank_name, sub_bank, qr_code_url, captured_at, purge_after) VALUES (?1,?2,?3,?4,?5,?6,?7,?8)"#)
+                    .bind(&o.order_number).bind(&name).bind(&no).bind(&bank).bind(&sub_bank).bind(&qr).bind(now)
+                    .bind(self.retention().purge_after(stage, now))
+                    .execute(&mut *tx).await?;
+            }
+        }
+        if prev != Some(stage) {
+            apply_retention(&mut tx, &o.order_number, self.retention().purge_after(stage, now)).await?;
+        }
+        tx.commit().await?;
+        if let Some(change) = OrderChange::stage(&o.order_number, prev, stage) { self.changes.emit(change); }
         Ok(())
     }
 
-    pub async fn list_orders(&self, limit: i64) -> Result<Vec<OrderRow>> {
-        let rows = sqlx::query(r#"SELECT order_number, trade_type, fiat, asset, amount_asset, total_fiat, price, order_status_code, buyer_nickname, seller_nickname, has_payment_detail, last_api_sync_ts FROM orders ORDER BY create_time_ms DESC LIMIT ?"#)
-            .bind(limit).fetch_all(&self.pool).await?;
+    /// Load every extension-sourced order with its payment detail (rehydrate OrderStore at startup)
+    pub async fn load_ext_orders(&self) -> Result<Vec<Order>> {
+        let rows = sqlx::query(r#"SELECT o.order_number, o.account_id, o.trade_type, o.asset, o.fiat, o.price, o.amount_asset, o.total_fiat, o.order_status_code, o.create_time_ms, o.buyer_nickname, o.seller_nickname, o.remark, o.last_ext_update_ts, p.account_name, p.account_no, p.bank_name, p.sub_bank, p.qr_code_url FROM orders o LEFT JOIN order_payment_detail p ON p.order_number = o.order_number WHERE (o.source_flags & ?1) != 0"#)
+            .bind(SOURCE_EXT).fetch_all(&self.pool).await?;
         let mut out = Vec::new();
         for r in rows {
-            let code: i64 = r.get("order_status_code");
-            out.push(OrderRow {
-                order_number: r.get("order_number"),
-                trade_type: r.get("trade_type"),
-                fiat: r.get("fiat"),
-                asset: r.get("asset"),
-                amount_asset: r.get("amount_asset"),
-                total_fiat: r.get("total_fiat"),
-                price: r.get("price"),
-                status_code: code,
-                status_label: map_status(code),
-                buyer_nickname: r.get("buyer_nickname"),
-                seller_nickname: r.get("seller_nickname"),
-                has_payment_detail: r.get::<i64,_>("has_payment_detail") == 1,
-                last_api_sync_ts: r.get("last_api_sync_ts")
+            let text = |col: &str| r.get::<Option<String>,_>(col).unwrap_or_default();
+            out.push(Order {
+                order_number: text("order_number"),
+                account_id: r.get("account_id"),
+                trade_type: text("trade_type"),
+                asset: text("asset"),
+                fiat: text("fiat"),
+                amount_asset: decimal_col(&r, "amount_asset"),
+                total_fiat: decimal_col(&r, "total_fiat"),
+                price: decimal_col(&r, "price"),
+                stage: OrderStage::from_code(r.get::<Option<i64>,_>("order_status_code").unwrap_or(0) as u8),
+                create_time_ms: r.get::<Option<i64>,_>("create_time_ms").unwrap_or(0),
+                buyer_nick: text("buyer_nickname"),
+                seller_nick: text("seller_nickname"),
+                account_name: self.open(r.get("account_name")),
+                account_no: self.open(r.get("account_no")),
+                bank_name: self.open(r.get("bank_name")),
+                sub_bank: self.open(r.get("sub_bank")),
+                qr_code: self.open(r.get("qr_code_url")),
+                remark: r.get("remark"),
+                expected_pay_time_ms: None,
+                last_update_ts: r.get::<Option<i64>,_>("last_ext_update_ts").unwrap_or(0)
             });
         }
         Ok(out)
     }
+
+    /// Encrypt detail rows written in plaintext before field encryption existed
+    pub async fn seal_plaintext_details(&self) -> Result<usize> {
+        let rows = sqlx::query("SELECT id, account_name, account_no, bank_name, sub_bank, qr_code_url FROM order_payment_detail").fetch_all(&self.pool).await?;
+        let mut tx = self.pool.begin().await?;
+        let mut n = 0;
+        for r in rows {
+            let cols: [Option<Vec<u8>>; 5] = [r.get("account_name"), r.get("account_no"), r.get("bank_name"), r.get("sub_bank"), r.get("qr_code_url")];
+            if cols.iter().flatten().all(|c| crypto::is_sealed(c)) { continue; }
+            let [name, no, bank, sub_bank, qr] = cols.map(|c| c.map(|v| if crypto::is_sealed(&v) { Ok(v) } else { self.crypto.encrypt(&v) }).transpose());
+            sqlx::query("UPDATE order_payment_detail SET account_name=?2, account_no=?3, bank_name=?4, sub_bank=?5, qr_code_url=?6 WHERE id=?1")
+                .bind(r.get::<i64,_>("id")).bind(name?).bind(no?).bind(bank?).bind(sub_bank?).bind(qr?)
+                .execute(&mut *tx).await?;
+            n += 1;
+        }
+        tx.commit().await?;
+        Ok(n)
+    }
+
+    /// Delete detail rows past `purge_after`; returns the affected order numbers
+    pub async fn purge_expired_details(&self, now: i64) -> Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let orders: Vec<String> = sqlx::query_scalar("SELECT DISTINCT order_number FROM order_payment_detail WHERE purge_after IS NOT NULL AND purge_after <= ?1")
+            .bind(now).fetch_all(&mut *tx).await?;
+        for no in &orders {
+            sqlx::query("DELETE FROM order_payment_detail WHERE order_number = ?1").bind(no).execute(&mut *tx).await?;
+            sqlx::query("UPDATE orders SET has_payment_detail = 0 WHERE order_number = ?1").bind(no).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(orders)
+    }
+
+    pub async fn timeline(&self, order_number: &str) -> Result<Option<OrderTimeline>> {
+        let Some(row) = sqlx::query(r#"SELECT create_time_ms, buyer_paid_time_ms, released_time_ms, cancelled_time_ms FROM orders WHERE order_number = ?1"#)
+            .bind(order_number).fetch_optional(&self.pool).await? else { return Ok(None) };
+        let events = sqlx::query(r#"SELECT from_code, to_code, source, observed_at FROM order_events WHERE order_number = ?1 ORDER BY observed_at ASC, id ASC"#)
+            .bind(order_number).fetch_all(&self.pool).await?
+            .into_iter().map(|r| {
+                let to_code: i64 = r.get("to_code");
+                OrderEvent {
+                    from_code: r.get("from_code"),
+                    to_code,
+                    to_label: stage_of(to_code).label(),
+                    source: r.get("source"),
+                    observed_at: r.get("observed_at")
+                }
+            }).collect();
+        Ok(Some(OrderTimeline {
+            order_number: order_number.to_string(),
+            create_time_ms: row.get("create_time_ms"),
+            buyer_paid_time_ms: row.get("buyer_paid_time_ms"),
+            released_time_ms: row.get("released_time_ms"),
+            cancelled_time_ms: row.get("cancelled_time_ms"),
+            events
+        }))
+    }
+
+    /// Newest orders first; `account_id` None = all accounts
+    pub async fn list_orders(&self, limit: i64, account_id: Option<i64>) -> Result<Vec<OrderRow>> {
+        let rows = sqlx::query(&format!("SELECT {ORDER_ROW_COLS} FROM orders WHERE (?2 IS NULL OR account_id = ?2) ORDER BY create_time_ms DESC LIMIT ?1"))
+            .bind(limit).bind(account_id).fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(order_row).collect())
+    }
+
+    /// Creation time of the account's oldest pending / paid / appealing order of one side
+    pub async fn oldest_open_order(&self, account_id: i64, trade_type: &str) -> Result<Option<i64>> {
+        let open = [OrderStage::Pending, OrderStage::Paid, OrderStage::Appealing].map(|s| s.code() as i64);
+        Ok(sqlx::query_scalar("SELECT MIN(create_time_ms) FROM orders WHERE account_id = ?1 AND trade_type = ?2 AND create_time_ms > 0 AND order_status_code IN (?3, ?4, ?5)")
+            .bind(account_id).bind(trade_type).bind(open[0]).bind(open[1]).bind(open[2])
+            .fetch_one(&self.pool).await?)
+    }
+
+    pub async fn get_order(&self, order_number: &str) -> Result<Option<OrderRow>> {
+        let row = sqlx::query(&format!("SELECT {ORDER_ROW_COLS} FROM orders WHERE order_number = ?1"))
+            .bind(order_number).fetch_optional(&self.pool).await?;
+        Ok(row.as_ref().map(order_row))
+    }
+
+    /// Exact sums (Decimal, not SQL REAL) per account + fiat
+    pub async fn account_totals(&self) -> Result<Vec<AccountTotals>> {
+        let rows = sqlx::query(r#"SELECT o.account_id, c.label, COALESCE(o.fiat, '') AS fiat, o.trade_type, o.order_status_code, o.price, o.amount_asset, o.total_fiat
+            FROM orders o LEFT JOIN api_credentials c ON c.id = o.account_id
+            ORDER BY o.account_id, fiat"#)
+            .fetch_all(&self.pool).await?;
+        let mut out: Vec<AccountTotals> = Vec::new();
+        for r in rows {
+            let account_id: Option<i64> = r.get("account_id");
+            let fiat: String = r.get("fiat");
+            let t = match out.last_mut() {
+                Some(t) if t.account_id == account_id && t.fiat == fiat => t,
+                _ => {
+                    out.push(AccountTotals {
+                        account_id, label: r.get("label"), fiat,
+                        orders: 0, completed: 0,
+                        buy_fiat: Decimal::ZERO, sell_fiat: Decimal::ZERO, buy_asset: Decimal::ZERO, sell_asset: Decimal::ZERO,
+                        mismatched: 0
+                    });
+                    out.last_mut().unwrap()
+                }
+            };
+            let (price, amount, total) = (decimal_col(&r, "price"), decimal_col(&r, "amount_asset"), decimal_col(&r, "total_fiat"));
+            t.orders += 1;
+            if decimal::amounts_mismatch(price, amount, total) { t.mismatched += 1; }
+            // Older rows may still hold 6 for completed
+            if stage_of(r.get::<Option<i64>,_>("order_status_code").unwrap_or(0)) != OrderStage::Completed { continue; }
+            t.completed += 1;
+            let (fiat_sum, asset_sum) = match r.get::<Option<String>,_>("trade_type").as_deref() {
+                Some("BUY") => (&mut t.buy_fiat, &mut t.buy_asset),
+                Some("SELL") => (&mut t.sell_fiat, &mut t.sell_asset),
+                _ => continue
+            };
+            *fiat_sum = fiat_sum.checked_add(total.unwrap_or(Decimal::ZERO)).ok_or_else(|| anyhow!("Fiat total overflow"))?;
+            *asset_sum = asset_sum.checked_add(amount.unwrap_or(Decimal::ZERO)).ok_or_else(|| anyhow!("Asset total overflow"))?;
+        }
+        Ok(out)
+    }
+
+    pub async fn account_for_nickname(&self, nickname: &str) -> Result<Option<i64>> {
+        Ok(sqlx::query_scalar("SELECT account_id FROM account_nicknames WHERE nickname = ?1")
+            .bind(nickname).fetch_optional(&self.pool).await?)
+    }
+
+    /// Remember which account a nickname belongs to and adopt its unassigned extension orders
+    pub async fn link_nickname(&self, nickname: &str, account_id: i64, source: NicknameSource, now: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO account_nicknames (nickname, account_id, updated_at, source) VALUES (?1, ?2, ?3, ?4) ON CONFLICT(nickname) DO UPDATE SET account_id = excluded.account_id, updated_at = excluded.updated_at, source = excluded.source")
+            .bind(nickname).bind(account_id).bind(now).bind(source.as_str())
+            .execute(&mut *tx).await?;
+        sqlx::query("UPDATE orders SET account_id = ?2 WHERE account_id IS NULL AND (buyer_nickname = ?1 OR seller_nickname = ?1)")
+            .bind(nickname).bind(account_id)
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Link a detected nickname. Known nicknames keep their account; a new one is only
+    /// given to the active profile when it comes from the profile endpoint and that account
+    /// has no nickname yet, so a browser logged into another Binance account never merges
+    /// two accounts. Pattern guesses stay unlinked until confirmed via `link_nickname`.
+    pub async fn auto_link_nickname(&self, nickname: &str, source: NicknameSource, now: i64) -> Result<Option<i64>> {
+        if let Some(id) = self.account_for_nickname(nickname).await? {
+            sqlx::query("UPDATE account_nicknames SET updated_at = ?2 WHERE nickname = ?1").bind(nickname).bind(now)
+                .execute(&self.pool).await?;
+            return Ok(Some(id));
+        }
+        if source != NicknameSource::Profile { return Ok(None); }
+        let active: Option<i64> = sqlx::query_scalar("SELECT COALESCE((SELECT credential_id FROM active_profile WHERE id = 1), (SELECT MAX(id) FROM api_credentials))")
+            .fetch_one(&self.pool).await?;
+        let Some(account) = active else { return Ok(None) };
+        let taken: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM account_nicknames WHERE account_id = ?1")
+            .bind(account).fetch_one(&self.pool).await?;
+        if taken > 0 { return Ok(None); }
+        self.link_nickname(nickname, account, source, now).await?;
+        Ok(Some(account))
+    }
+
+    /// Every linked nickname, most recently seen first
+    pub async fn nicknames(&self) -> Result<Vec<(String, NicknameSource)>> {
+        let rows = sqlx::query("SELECT nickname, source FROM account_nicknames ORDER BY updated_at DESC")
+            .fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|r| (r.get("nickname"), NicknameSource::from_db(r.get("source")))).collect())
+    }
 }
 
-fn map_status(code: i64) -> String {
-    match code {
-        1 => "Đang chờ xử lý".into(),
-        2 => "Người mua đã thanh toán".into(),
-        4 | 6 => "Đã hoàn thành".into(),
-        5 => "Đã hủy".into(),
-        _ => format!("Code{}", code)
+const ORDER_ROW_COLS: &str = "order_number, account_id, trade_type, fiat, asset, amount_asset, total_fiat, price, order_status_code, buyer_nickname, seller_nickname, has_payment_detail, last_api_sync_ts";
+
+fn order_row(r: &SqliteRow) -> OrderRow {
+    let code: i64 = r.get("order_status_code");
+    let (price, amount_asset, total_fiat) = (decimal_col(r, "price"), decimal_col(r, "amount_asset"), decimal_col(r, "total_fiat"));
+    OrderRow {
+        order_number: r.get("order_number"),
+        account_id: r.get("account_id"),
+        trade_type: r.get("trade_type"),
+        fiat: r.get("fiat"),
+        asset: r.get("asset"),
+        amount_asset,
+        total_fiat,
+        price,
+        amount_mismatch: decimal::amounts_mismatch(price, amount_asset, total_fiat),
+        status_code: code,
+        status_label: stage_of(code).label(),
+        buyer_nickname: r.get("buyer_nickname"),
+        seller_nickname: r.get("seller_nickname"),
+        has_payment_detail: r.get::<i64,_>("has_payment_detail") == 1,
+        last_api_sync_ts: r.get::<Option<i64>,_>("last_api_sync_ts").unwrap_or(0)
+    }
+}
+
+fn stage_of(code: i64) -> OrderStage {
+    OrderStage::from_code(code.clamp(0, 255) as u8)
+}
+
+/// Apply the stage transition table against what is already stored for this order.
+/// Returns (stored stage, stage to write).
+async fn guard_stage(conn: &mut SqliteConnection, order_number: &str, incoming: OrderStage) -> Result<(Option<OrderStage>, OrderStage)> {
+    let current: Option<Option<i64>> = sqlx::query_scalar("SELECT order_status_code FROM orders WHERE order_number = ?1")
+        .bind(order_number).fetch_optional(&mut *conn).await?;
+    Ok(match current.flatten().map(stage_of) {
+        Some(prev) => (Some(prev), prev.advance(incoming, order_number)),
+        None => (None, incoming)
+    })
+}
+
+async fn apply_retention(conn: &mut SqliteConnection, order_number: &str, purge_after: Option<i64>) -> Result<()> {
+    sqlx::query("UPDATE order_payment_detail SET purge_after = ?2 WHERE order_number = ?1")
+        .bind(order_number).bind(purge_after)
+        .execute(&mut *conn).await?;
+    Ok(())
+}
+
+/// Append to `order_events` and fill the milestone column the first time a stage is reached.
+/// Only an observed transition sets a milestone: an order first seen already paid / finished
+/// (initial sync, backfill) got there at an unknown time, so the column stays NULL.
+async fn record_stage_change(conn: &mut SqliteConnection, order_number: &str, prev: Option<OrderStage>, next: OrderStage, source: &str, ts: i64) -> Result<()> {
+    if prev == Some(next) || matches!(next, OrderStage::Unknown(_)) { return Ok(()); }
+    sqlx::query(r#"INSERT INTO order_events (order_number, from_code, to_code, source, observed_at) VALUES (?1,?2,?3,?4,?5)"#)
+        .bind(order_number).bind(prev.map(|p| p.code() as i64)).bind(next.code() as i64).bind(source).bind(ts)
+        .execute(&mut *conn).await?;
+    if prev.is_none() { return Ok(()); }
+    let milestone = match next {
+        OrderStage::Paid => "buyer_paid_time_ms",
+        OrderStage::Completed => "released_time_ms",
+        OrderStage::Cancelled | OrderStage::CancelledBySystem => "cancelled_time_ms",
+        _ => return Ok(())
+    };
+    sqlx::query(&format!("UPDATE orders SET {milestone} = COALESCE({milestone}, ?2) WHERE order_number = ?1"))
+        .bind(order_number).bind(ts)
+        .execute(&mut *conn).await?;
+    Ok(())
+}
+
+/// Decimal column; NULL or unparsable text -> None
+fn decimal_col(r: &SqliteRow, col: &str) -> Option<Decimal> {
+    r.try_get::<Option<Decimal>,_>(col).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn test_repo(name: &str) -> OrderRepo {
+        let path = std::env::temp_dir().join(format!("p2p-repo-{}-{name}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = crate::db::Db::init(&path).await.unwrap();
+        OrderRepo::new(db.pool().clone(), CryptoCtx::locked())
+    }
+
+    fn api_order(no: &str, status: &str) -> serde_json::Value {
+        json!({"orderNumber": no, "tradeType": "BUY", "asset": "USDT", "fiat": "VND", "amount": "1", "totalPrice": "25500",
+            "unitPrice": "25500", "orderStatus": status, "createTime": 1_000, "counterPartNickName": "cp"})
+    }
+
+    #[tokio::test]
+    async fn first_seen_finished_order_has_no_milestone() {
+        let repo = test_repo("first-seen").await;
+        repo.upsert_from_api(&api_order("1", "COMPLETED"), 1, 5_000).await.unwrap();
+        let t = repo.timeline("1").await.unwrap().unwrap();
+        assert_eq!(t.released_time_ms, None);
+        assert_eq!(t.buyer_paid_time_ms, None);
+    }
+
+    #[tokio::test]
+    async fn observed_transition_sets_milestone() {
+        let repo = test_repo("observed").await;
+        repo.upsert_from_api(&api_order("1", "TRADING"), 1, 5_000).await.unwrap();
+        repo.upsert_from_api(&api_order("1", "BUYER_PAYED"), 1, 6_000).await.unwrap();
+        repo.upsert_from_api(&api_order("1", "COMPLETED"), 1, 7_000).await.unwrap();
+        // Re-seen later: first time reached wins
+        repo.upsert_from_api(&api_order("1", "COMPLETED"), 1, 9_000).await.unwrap();
+        let t = repo.timeline("1").await.unwrap().unwrap();
+        assert_eq!(t.buyer_paid_time_ms, Some(6_000));
+        assert_eq!(t.released_time_ms, Some(7_000));
+        assert_eq!(t.cancelled_time_ms, None);
+    }
+
+    #[tokio::test]
+    async fn oldest_open_order_skips_finished() {
+        let repo = test_repo("oldest-open").await;
+        let mut o = api_order("1", "COMPLETED"); o["createTime"] = json!(100);
+        repo.upsert_from_api(&o, 1, 5_000).await.unwrap();
+        let mut o = api_order("2", "BUYER_PAYED"); o["createTime"] = json!(200);
+        repo.upsert_from_api(&o, 1, 5_000).await.unwrap();
+        let mut o = api_order("3", "TRADING"); o["createTime"] = json!(300);
+        repo.upsert_from_api(&o, 1, 5_000).await.unwrap();
+        assert_eq!(repo.oldest_open_order(1, "BUY").await.unwrap(), Some(200));
+        assert_eq!(repo.oldest_open_order(1, "SELL").await.unwrap(), None);
+        assert_eq!(repo.oldest_open_order(2, "BUY").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn only_profile_nickname_is_auto_linked() {
+        let repo = test_repo("auto-link").await;
+        sqlx::query("INSERT INTO api_credentials (id, label) VALUES (7, 'main')").execute(&repo.pool).await.unwrap();
+        assert_eq!(repo.auto_link_nickname("guess", NicknameSource::Pattern, 1).await.unwrap(), None);
+        assert_eq!(repo.account_for_nickname("guess").await.unwrap(), None);
+        assert_eq!(repo.auto_link_nickname("me", NicknameSource::Profile, 2).await.unwrap(), Some(7));
+        // confirmed pattern guess keeps its link on later detections
+        repo.link_nickname("alt", 7, NicknameSource::Manual, 3).await.unwrap();
+        assert_eq!(repo.auto_link_nickname("alt", NicknameSource::Pattern, 4).await.unwrap(), Some(7));
     }
 }
@@ -1,15 +1,203 @@
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+use serde_json::Value;
+
+/// Binance C2C order states.
+///
+/// Numeric codes (bapi `orderStatus`) keep the meaning the app has always stored in
+/// `orders.order_status_code`: 1 pending, 2 buyer paid, 4 / 6 completed, 5 cancelled.
+/// 3 (appealing) and 7 (cancelled by system) used to show as `Code{n}`; they are new
+/// here, so no stored row changes meaning. The sapi history endpoint returns the
+/// same states as strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum OrderStage {
-    Code(u8)
+    Pending,
+    Paid,
+    Appealing,
+    Completed,
+    Cancelled,
+    CancelledBySystem,
+    Unknown(u8)
 }
 
 impl OrderStage {
     pub fn from_code(code: u8) -> Self {
-        OrderStage::Code(code)
+        match code {
+            1 => OrderStage::Pending,
+            2 => OrderStage::Paid,
+            3 => OrderStage::Appealing,
+            4 | 6 => OrderStage::Completed,
+            5 => OrderStage::Cancelled,
+            7 => OrderStage::CancelledBySystem,
+            c => OrderStage::Unknown(c)
+        }
+    }
+
+    pub fn from_api_str(s: &str) -> Self {
+        match s {
+            "PENDING" | "TRADING" => OrderStage::Pending,
+            "BUYER_PAYED" | "BUYER_PAID" => OrderStage::Paid,
+            "IN_APPEAL" | "APPEALING" => OrderStage::Appealing,
+            // Paid, seller releasing the crypto
+            "DISTRIBUTING" => OrderStage::Paid,
+            "COMPLETED" => OrderStage::Completed,
+            "CANCELLED" => OrderStage::Cancelled,
+            "CANCELLED_BY_SYSTEM" => OrderStage::CancelledBySystem,
+            _ => OrderStage::Unknown(0)
+        }
+    }
+
+    /// `orderStatus` may be numeric (bapi, captured by the extension) or a string (sapi)
+    pub fn from_api_value(v: Option<&Value>) -> Self {
+        match v {
+            Some(Value::Number(n)) => n.as_u64().map(|c| Self::from_code(c.min(255) as u8)).unwrap_or(OrderStage::Unknown(0)),
+            Some(Value::String(s)) => Self::from_api_str(s),
+            _ => OrderStage::Unknown(0)
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            OrderStage::Pending => 1,
+            OrderStage::Paid => 2,
+            OrderStage::Appealing => 3,
+            OrderStage::Completed => 4,
+            OrderStage::Cancelled => 5,
+            OrderStage::CancelledBySystem => 7,
+            OrderStage::Unknown(c) => *c
+        }
     }
+
     pub fn label(&self) -> String {
         match self {
-            OrderStage::Code(c) => format!("Code{}", c)
+            OrderStage::Pending => "Đang chờ xử lý".into(),
+            OrderStage::Paid => "Người mua đã thanh toán".into(),
+            OrderStage::Appealing => "Đang khiếu nại".into(),
+            OrderStage::Completed => "Đã hoàn thành".into(),
+            OrderStage::Cancelled => "Đã hủy".into(),
+            OrderStage::CancelledBySystem => "Hệ thống đã hủy".into(),
+            OrderStage::Unknown(c) => format!("Code{}", c)
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, OrderStage::Completed | OrderStage::Cancelled | OrderStage::CancelledBySystem)
+    }
+
+    /// Transition table. Staying in the same stage is always allowed, terminal
+    /// stages never move, and any known stage may replace an unknown one.
+    pub fn can_transition_to(&self, next: &OrderStage) -> bool {
+        use OrderStage::*;
+        if self == next { return true; }
+        if self.is_terminal() { return false; }
+        match (self, next) {
+            (Unknown(_), _) => true,
+            (_, Unknown(_)) => false,
+            (Pending, Paid | Appealing | Completed | Cancelled | CancelledBySystem) => true,
+            (Paid, Appealing | Completed | Cancelled | CancelledBySystem) => true,
+            (Appealing, Paid | Completed | Cancelled) => true,
+            _ => false
+        }
+    }
+
+    /// Resolve the stage to keep when `next` is observed for an order currently at `self`.
+    /// Impossible transitions (typically stale captures) are logged and ignored.
+    pub fn advance(&self, next: OrderStage, order_number: &str) -> OrderStage {
+        if self.can_transition_to(&next) {
+            next
+        } else if matches!(next, OrderStage::Unknown(_)) {
+            // Missing/unparsed status: keep what we know without noise
+            *self
+        } else {
+            eprintln!("[STAGE] Rejected {order_number}: {:?} -> {:?}", self, next);
+            *self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::OrderStage::*;
+    use serde_json::json;
+
+    const KNOWN: [OrderStage; 6] = [Pending, Paid, Appealing, Completed, Cancelled, CancelledBySystem];
+
+    #[test]
+    fn allowed_transitions() {
+        for (from, to) in [
+            (Pending, Paid), (Pending, Appealing), (Pending, Completed), (Pending, Cancelled), (Pending, CancelledBySystem),
+            (Paid, Appealing), (Paid, Completed), (Paid, Cancelled), (Paid, CancelledBySystem),
+            (Appealing, Paid), (Appealing, Completed), (Appealing, Cancelled),
+        ] {
+            assert!(from.can_transition_to(&to), "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn rejected_transitions() {
+        for (from, to) in [
+            (Paid, Pending), (Appealing, Pending), (Appealing, CancelledBySystem),
+            (Completed, Pending), (Completed, Paid), (Completed, Cancelled),
+            (Cancelled, Completed), (CancelledBySystem, Paid),
+        ] {
+            assert!(!from.can_transition_to(&to), "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn same_stage_always_allowed() {
+        for s in KNOWN.into_iter().chain([Unknown(9)]) {
+            assert!(s.can_transition_to(&s));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unknown_on_either_side() {
+        for s in KNOWN {
+            assert!(Unknown(0).can_transition_to(&s), "Unknown -> {s:?}");
+            assert!(!s.can_transition_to(&Unknown(0)), "{s:?} -> Unknown");
+        }
+        assert!(Unknown(0).can_transition_to(&Unknown(9)));
+    }
+
+    #[test]
+    fn advance_keeps_current_on_rejection() {
+        assert_eq!(Completed.advance(Pending, "1"), Completed);
+        assert_eq!(Paid.advance(Unknown(0), "1"), Paid);
+        assert_eq!(Appealing.advance(Paid, "1"), Paid);
+    }
+
+    #[test]
+    fn stored_codes_keep_their_meaning() {
+        // Codes written by the app before the stage enum: 1 pending, 2 paid, 4 / 6 completed, 5 cancelled
+        assert_eq!(OrderStage::from_code(1), Pending);
+        assert_eq!(OrderStage::from_code(2), Paid);
+        assert_eq!(OrderStage::from_code(4), Completed);
+        assert_eq!(OrderStage::from_code(5), Cancelled);
+        assert_eq!(OrderStage::from_code(6), Completed);
+        assert_eq!(OrderStage::from_code(3), Appealing);
+        assert_eq!(OrderStage::from_code(7), CancelledBySystem);
+        assert_eq!(OrderStage::from_code(42), Unknown(42));
+    }
+
+    #[test]
+    fn code_round_trips() {
+        for s in KNOWN.into_iter().chain([Unknown(42)]) {
+            assert_eq!(OrderStage::from_code(s.code()), s);
+        }
+    }
+
+    #[test]
+    fn api_values() {
+        assert_eq!(OrderStage::from_api_value(Some(&json!(4))), Completed);
+        assert_eq!(OrderStage::from_api_value(Some(&json!(6))), Completed);
+        assert_eq!(OrderStage::from_api_value(Some(&json!(5))), Cancelled);
+        assert_eq!(OrderStage::from_api_value(Some(&json!(1000))), Unknown(255));
+        assert_eq!(OrderStage::from_api_value(Some(&json!("COMPLETED"))), Completed);
+        assert_eq!(OrderStage::from_api_value(Some(&json!("BUYER_PAYED"))), Paid);
+        assert_eq!(OrderStage::from_api_value(Some(&json!("DISTRIBUTING"))), Paid);
+        assert_eq!(OrderStage::from_api_value(Some(&json!("TRADING"))), Pending);
+        assert_eq!(OrderStage::from_api_value(Some(&json!("CANCELLED_BY_SYSTEM"))), CancelledBySystem);
+        assert_eq!(OrderStage::from_api_value(Some(&json!("???"))), Unknown(0));
+        assert_eq!(OrderStage::from_api_value(None), Unknown(0));
+    }
+}
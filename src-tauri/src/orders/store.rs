@@ -1,23 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use tokio::sync::RwLock;
 use serde::Serialize;
+use anyhow::Result;
 
+use super::changes::{ChangeFeed, OrderChange, OrderChangeKind};
+use super::decimal::{self, Decimal};
+use super::nickname::NicknameSource;
 use super::status::OrderStage;
 use super::parser_list::{OrderSummaryParsed, PaymentField as ListField};
 use super::parser_detail::{OrderDetailParsed, PaymentField as DetailField};
+use super::repo::OrderRepo;
 
 #[derive(Debug, Clone)]
 pub struct Order {
     pub order_number: String,
+    // Merchant account (credential profile id), None until the nickname is linked
+    pub account_id: Option<i64>,
     pub trade_type: String,
     pub asset: String,
     pub fiat: String,
-    pub amount_asset: String,
-    pub total_fiat: String,
-    pub price: String,
+    pub amount_asset: Option<Decimal>,
+    pub total_fiat: Option<Decimal>,
+    pub price: Option<Decimal>,
     pub stage: OrderStage,
-    #[allow(dead_code)]
-    pub create_time_ms: i64, // Reserved for future use
+    pub create_time_ms: i64,
     pub buyer_nick: String,
     pub seller_nick: String,
     pub account_name: Option<String>,
@@ -33,19 +40,40 @@ pub struct Order {
 #[derive(Default)]
 pub struct OrderStore {
     inner: RwLock<HashMap<String, Order>>,
-    my_nickname: RwLock<Option<String>>,
+    // Nickname the extension is currently logged in as, and how we know it
+    my_nickname: RwLock<Option<(String, NicknameSource)>>,
+    // Account the extension is currently logged into (resolved from `my_nickname`)
+    account_id: RwLock<Option<i64>>,
+    // Every nickname linked to one of our accounts (drives `side_role`)
+    known_nicknames: RwLock<HashSet<String>>,
+    // Write-through persistence (None = in-memory only)
+    repo: Option<Arc<OrderRepo>>,
+    // Payment details captured; same feed as the repo's
+    changes: ChangeFeed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MyNickname {
+    pub nickname: Option<String>,
+    pub source: Option<NicknameSource>,
+    pub account_id: Option<i64>,
+    // Guessed from order traffic and not linked yet: confirm with `set_my_nickname`
+    pub needs_confirmation: bool
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct OrderView {
     pub order_number: String,
+    pub account_id: Option<i64>,
     pub side_role: String,
     pub trade_type: String,
     pub fiat: String,
     pub asset: String,
-    pub amount_asset: String,
-    pub total_fiat: String,
-    pub price: String,
+    pub amount_asset: Option<Decimal>,
+    pub total_fiat: Option<Decimal>,
+    pub price: Option<Decimal>,
+    // price * amount không khớp total_fiat
+    pub amount_mismatch: bool,
     pub stage_label: String,
     pub account_name: String,
     pub account_no: String,
@@ -54,21 +82,181 @@ pub struct OrderView {
 }
 
 impl OrderStore {
-    pub async fn set_my_nickname(&self, nick: String) {
-        *self.my_nickname.write().await = Some(nick);
+    pub fn with_repo(repo: Arc<OrderRepo>) -> Self {
+        Self { changes: repo.changes().clone(), repo: Some(repo), ..Default::default() }
+    }
+
+    /// Load persisted extension orders back into memory (startup, and again after unlock
+    /// to pick up the decrypted payment details). Orders already in memory only get
+    /// their missing payment fields filled in.
+    pub async fn rehydrate(&self) -> Result<usize> {
+        let Some(repo) = &self.repo else { return Ok(0) };
+        let orders = repo.load_ext_orders().await?;
+        let n = orders.len();
+        let mut g = self.inner.write().await;
+        for o in orders {
+            match g.get_mut(&o.order_number) {
+                Some(cur) => {
+                    cur.account_id = cur.account_id.or(o.account_id);
+                    cur.account_name = cur.account_name.take().or(o.account_name);
+                    cur.account_no = cur.account_no.take().or(o.account_no);
+                    cur.bank_name = cur.bank_name.take().or(o.bank_name);
+                    cur.sub_bank = cur.sub_bank.take().or(o.sub_bank);
+                    cur.qr_code = cur.qr_code.take().or(o.qr_code);
+                }
+                None => { g.insert(o.order_number.clone(), o); }
+            }
+        }
+        drop(g);
+        let nicknames = repo.nicknames().await?;
+        self.known_nicknames.write().await.extend(nicknames.iter().map(|(nick, _)| nick.clone()));
+        // Until the extension tells us otherwise, assume the most recently seen login
+        if self.my_nickname.read().await.is_none() {
+            if let Some((nick, source)) = nicknames.into_iter().next() {
+                self.set_my_nickname(nick, source).await?;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Write payment details captured while the vault was locked
+    pub async fn flush_payment_details(&self, ts: i64) -> Result<usize> {
+        let pending: Vec<Order> = self.inner.read().await.values()
+            .filter(|o| o.account_no.is_some() || o.account_name.is_some()).cloned().collect();
+        self.persist(&pending, ts).await?;
+        Ok(pending.len())
     }
 
-    pub async fn upsert_summaries(&self, list: Vec<OrderSummaryParsed>, ts: i64) {
+    /// Drop purged payment details from memory
+    pub async fn clear_payment_details(&self, order_numbers: &[String]) {
         let mut g = self.inner.write().await;
+        for no in order_numbers {
+            if let Some(o) = g.get_mut(no) {
+                o.account_name = None;
+                o.account_no = None;
+                o.bank_name = None;
+                o.sub_bank = None;
+                o.qr_code = None;
+            }
+        }
+    }
+
+    // Called without holding `inner`: readers never wait on SQLite
+    async fn persist(&self, orders: &[Order], ts: i64) -> Result<()> {
+        if let Some(repo) = &self.repo {
+            for o in orders { repo.upsert_from_ext(o, ts).await?; }
+        }
+        Ok(())
+    }
+
+    /// Store persisted copies; a stage another writer advanced in the meantime is kept
+    async fn commit(&self, orders: Vec<Order>) {
+        let mut g = self.inner.write().await;
+        for mut o in orders {
+            if let Some(cur) = g.get(&o.order_number) {
+                if !cur.stage.can_transition_to(&o.stage) { o.stage = cur.stage; }
+            }
+            g.insert(o.order_number.clone(), o);
+        }
+    }
+
+    /// Stage written by the API sync, so the next capture is checked against it
+    pub async fn observe_api_stage(&self, order_number: &str, stage: OrderStage) {
+        if let Some(o) = self.inner.write().await.get_mut(order_number) {
+            o.stage = stage;
+        }
+    }
+
+    /// Set the logged-in nickname; new captures are tagged with the account it is linked to
+    async fn set_my_nickname(&self, nick: String, source: NicknameSource) -> Result<Option<i64>> {
+        let account = match &self.repo {
+            Some(repo) => repo.account_for_nickname(&nick).await?,
+            None => None
+        };
+        *self.account_id.write().await = account;
+        *self.my_nickname.write().await = Some((nick.clone(), source));
+        if account.is_some() {
+            self.known_nicknames.write().await.insert(nick.clone());
+            // Orders captured before the nickname was known
+            let mut g = self.inner.write().await;
+            for o in g.values_mut().filter(|o| o.account_id.is_none() && (o.buyer_nick == nick || o.seller_nick == nick)) {
+                o.account_id = account;
+            }
+        }
+        Ok(account)
+    }
+
+    /// Profile deleted: stop tagging captures with it and unassign its orders
+    pub async fn forget_account(&self, account_id: i64) -> Result<()> {
+        if let Some(repo) = &self.repo {
+            let nicknames = repo.nicknames().await?;
+            *self.known_nicknames.write().await = nicknames.into_iter().map(|(nick, _)| nick).collect();
+        }
+        let mut current = self.account_id.write().await;
+        if *current == Some(account_id) { *current = None; }
+        drop(current);
+        for o in self.inner.write().await.values_mut().filter(|o| o.account_id == Some(account_id)) {
+            o.account_id = None;
+        }
+        Ok(())
+    }
+
+    /// Nickname detected from captured traffic. The profile endpoint is authoritative;
+    /// the order pattern never replaces a manual override and a new pattern nickname is
+    /// only a suggestion until the operator confirms it.
+    pub async fn observe_my_nickname(&self, nick: String, source: NicknameSource, ts: i64) -> Result<()> {
+        match &*self.my_nickname.read().await {
+            Some((cur, _)) if *cur == nick => return Ok(()),
+            Some((_, NicknameSource::Manual)) if source == NicknameSource::Pattern => return Ok(()),
+            _ => {}
+        }
+        if let Some(repo) = &self.repo {
+            repo.auto_link_nickname(&nick, source, ts).await?;
+        }
+        let account = self.set_my_nickname(nick.clone(), source).await?;
+        match account {
+            None if source == NicknameSource::Pattern => println!("[NICK] Suggested nickname '{nick}' (pattern), waiting for confirmation"),
+            _ => println!("[NICK] Detected my nickname '{nick}' ({}) -> account {account:?}", source.as_str())
+        }
+        Ok(())
+    }
+
+    /// Operator override: `nick` is the Binance nickname of `account_id`
+    pub async fn override_my_nickname(&self, nick: String, account_id: i64, ts: i64) -> Result<()> {
+        if let Some(repo) = &self.repo {
+            repo.link_nickname(&nick, account_id, NicknameSource::Manual, ts).await?;
+        }
+        self.known_nicknames.write().await.insert(nick.clone());
+        self.set_my_nickname(nick, NicknameSource::Manual).await?;
+        Ok(())
+    }
+
+    pub async fn my_nickname(&self) -> MyNickname {
+        let me = self.my_nickname.read().await.clone();
+        let account_id = *self.account_id.read().await;
+        MyNickname {
+            nickname: me.as_ref().map(|(n, _)| n.clone()),
+            source: me.as_ref().map(|(_, s)| *s),
+            account_id,
+            needs_confirmation: account_id.is_none() && matches!(me, Some((_, NicknameSource::Pattern)))
+        }
+    }
+
+    pub async fn upsert_summaries(&self, list: Vec<OrderSummaryParsed>, ts: i64) -> Result<()> {
+        let account = *self.account_id.read().await;
+        let mut captured = Vec::new();
+        let mut merged = Vec::with_capacity(list.len());
+        let g = self.inner.read().await;
         for s in list {
-            let entry = g.entry(s.order_number.clone()).or_insert_with(|| Order {
+            let mut entry = g.get(&s.order_number).cloned().unwrap_or_else(|| Order {
                 order_number: s.order_number.clone(),
+                account_id: account,
                 trade_type: s.trade_type.clone(),
                 asset: s.asset.clone(),
                 fiat: s.fiat.clone(),
-                amount_asset: s.amount_asset.clone(),
-                total_fiat: s.total_fiat.clone(),
-                price: s.price.clone(),
+                amount_asset: s.amount_asset,
+                total_fiat: s.total_fiat,
+                price: s.price,
                 stage: OrderStage::from_code(s.order_status_raw),
                 create_time_ms: s.create_time_ms,
                 buyer_nick: s.buyer_nick.clone(),
@@ -78,28 +266,39 @@ impl OrderStore {
                 last_update_ts: ts,
             });
 
-            entry.stage = OrderStage::from_code(s.order_status_raw);
-            entry.amount_asset = s.amount_asset;
-            entry.total_fiat = s.total_fiat;
-            entry.price = s.price;
+            entry.account_id = entry.account_id.or(account);
+            entry.stage = entry.stage.advance(OrderStage::from_code(s.order_status_raw), &s.order_number);
+            entry.amount_asset = s.amount_asset.or(entry.amount_asset);
+            entry.total_fiat = s.total_fiat.or(entry.total_fiat);
+            entry.price = s.price.or(entry.price);
             entry.last_update_ts = ts;
 
             if let Some(fields) = s.payment_fields {
-                self.apply_list_fields(entry, &fields);
+                let before = payment_key(&entry);
+                self.apply_list_fields(&mut entry, &fields);
+                if payment_captured(&before, &entry) { captured.push(s.order_number.clone()); }
             }
+            merged.push(entry);
         }
+        drop(g);
+        self.persist(&merged, ts).await?;
+        self.commit(merged).await;
+        self.emit_captured(captured);
+        Ok(())
     }
 
-    pub async fn upsert_detail(&self, d: OrderDetailParsed, ts: i64) {
-        let mut g = self.inner.write().await;
-        let entry = g.entry(d.order_number.clone()).or_insert_with(|| Order {
+    pub async fn upsert_detail(&self, d: OrderDetailParsed, ts: i64) -> Result<()> {
+        let account = *self.account_id.read().await;
+        let cur = self.inner.read().await.get(&d.order_number).cloned();
+        let mut entry = cur.unwrap_or_else(|| Order {
             order_number: d.order_number.clone(),
+            account_id: account,
             trade_type: "".into(),
             asset: "".into(),
             fiat: "".into(),
-            amount_asset: "".into(),
-            total_fiat: "".into(),
-            price: "".into(),
+            amount_asset: None,
+            total_fiat: None,
+            price: None,
             stage: OrderStage::from_code(d.order_status_raw),
             create_time_ms: 0,
             buyer_nick: "".into(),
@@ -109,50 +308,56 @@ impl OrderStore {
             last_update_ts: ts,
         });
 
-        entry.stage = OrderStage::from_code(d.order_status_raw);
+        entry.account_id = entry.account_id.or(account);
+        entry.stage = entry.stage.advance(OrderStage::from_code(d.order_status_raw), &d.order_number);
         entry.remark = d.remark;
         entry.expected_pay_time_ms = d.expected_pay_time_ms;
         entry.last_update_ts = ts;
-        self.apply_detail_fields(entry, &d.payment_fields);
+        let before = payment_key(&entry);
+        self.apply_detail_fields(&mut entry, &d.payment_fields);
+        let captured = payment_captured(&before, &entry);
+        self.persist(std::slice::from_ref(&entry), ts).await?;
+        self.commit(vec![entry]).await;
+        if captured { self.emit_captured(vec![d.order_number]); }
+        Ok(())
+    }
+
+    // After persist, so listeners re-reading the repo see the new row
+    fn emit_captured(&self, order_numbers: Vec<String>) {
+        for order_number in order_numbers {
+            self.changes.emit(OrderChange { order_number, kind: OrderChangeKind::PaymentDetailsCaptured });
+        }
     }
 
     #[allow(dead_code)]
-    pub async fn quick_update_status(&self, order_number: &str, code: u8, ts: i64) {
+    pub async fn quick_update_status(&self, order_number: &str, code: u8, ts: i64) -> Result<()> {
         // Reserved for future use - quick status updates without full re-parse
-        let mut g = self.inner.write().await;
-        if let Some(o) = g.get_mut(order_number) {
-            o.stage = OrderStage::from_code(code);
-            o.last_update_ts = ts;
-        }
+        let Some(mut o) = self.inner.read().await.get(order_number).cloned() else { return Ok(()) };
+        o.stage = o.stage.advance(OrderStage::from_code(code), order_number);
+        o.last_update_ts = ts;
+        self.persist(std::slice::from_ref(&o), ts).await?;
+        self.commit(vec![o]).await;
+        Ok(())
     }
 
-    pub async fn list(&self) -> Vec<OrderView> {
+    /// `account_id` None = every account
+    pub async fn list(&self, account_id: Option<i64>) -> Vec<OrderView> {
         let g = self.inner.read().await;
-        let me = self.my_nickname.read().await.clone();
+        let mine = self.my_nicknames().await;
+        g.values().filter(|o| account_id.is_none() || o.account_id == account_id).map(|o| view_of(o, &mine)).collect()
+    }
 
-        g.values().map(|o| {
-            let side_role = if let Some(ref mine) = me {
-                if o.buyer_nick == *mine { "YOU_BUY" }
-                else if o.seller_nick == *mine { "YOU_SELL" }
-                else { "OTHER" }
-            } else { "UNKNOWN" };
-
-            OrderView {
-                order_number: o.order_number.clone(),
-                side_role: side_role.into(),
-                trade_type: o.trade_type.clone(),
-                fiat: o.fiat.clone(),
-                asset: o.asset.clone(),
-                amount_asset: o.amount_asset.clone(),
-                total_fiat: o.total_fiat.clone(),
-                price: o.price.clone(),
-                stage_label: o.stage.label(),
-                account_name: o.account_name.clone().unwrap_or_default(),
-                account_no: o.account_no.clone().unwrap_or_default(),
-                bank_name: o.bank_name.clone().unwrap_or_default(),
-                last_update_ts: o.last_update_ts
-            }
-        }).collect()
+    /// One order as `list` shows it; None if the extension has not seen it
+    pub async fn view(&self, order_number: &str) -> Option<OrderView> {
+        let g = self.inner.read().await;
+        let o = g.get(order_number)?;
+        Some(view_of(o, &self.my_nicknames().await))
+    }
+
+    async fn my_nicknames(&self) -> HashSet<String> {
+        let mut mine = self.known_nicknames.read().await.clone();
+        if let Some((nick, _)) = self.my_nickname.read().await.clone() { mine.insert(nick); }
+        mine
     }
 
     fn apply_list_fields(&self, entry: &mut Order, fields: &[ListField]) {
@@ -180,4 +385,40 @@ impl OrderStore {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn view_of(o: &Order, mine: &HashSet<String>) -> OrderView {
+    let side_role = if mine.is_empty() { "UNKNOWN" }
+        else if mine.contains(&o.buyer_nick) { "YOU_BUY" }
+        else if mine.contains(&o.seller_nick) { "YOU_SELL" }
+        else { "OTHER" };
+
+    OrderView {
+        order_number: o.order_number.clone(),
+        account_id: o.account_id,
+        side_role: side_role.into(),
+        trade_type: o.trade_type.clone(),
+        fiat: o.fiat.clone(),
+        asset: o.asset.clone(),
+        amount_asset: o.amount_asset,
+        total_fiat: o.total_fiat,
+        price: o.price,
+        amount_mismatch: decimal::amounts_mismatch(o.price, o.amount_asset, o.total_fiat),
+        stage_label: o.stage.label(),
+        account_name: o.account_name.clone().unwrap_or_default(),
+        account_no: o.account_no.clone().unwrap_or_default(),
+        bank_name: o.bank_name.clone().unwrap_or_default(),
+        last_update_ts: o.last_update_ts
+    }
+}
+
+type PaymentKey = (Option<String>, Option<String>, Option<String>);
+
+fn payment_key(o: &Order) -> PaymentKey {
+    (o.account_name.clone(), o.account_no.clone(), o.bank_name.clone())
+}
+
+// New or different payee / account / bank after a capture (a re-capture of the same values is not news)
+fn payment_captured(before: &PaymentKey, o: &Order) -> bool {
+    (o.account_name.is_some() || o.account_no.is_some()) && *before != payment_key(o)
+}
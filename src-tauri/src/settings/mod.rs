@@ -0,0 +1,163 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use anyhow::{Result, Context, bail};
+
+use crate::orders::repo::RetentionPolicy;
+
+/// File name inside the app config dir
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// Per-machine settings (`settings.toml`). Missing keys take the defaults below.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub ws: WsSettings,
+    pub sync: SyncSettings,
+    pub ingest: IngestSettings,
+    pub retention: RetentionPolicy,
+    pub db: DbSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WsSettings {
+    // Địa chỉ bridge cho extension; cần restart
+    pub addr: String,
+    // Số raw message giữ lại cho get_raw_messages
+    pub buffer_size: usize,
+    // Origin được phép mở WS (exact, hoặc prefix nếu kết thúc bằng `*`)
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    // Chu kỳ incremental sync nền (giây, 0 = tắt)
+    pub interval_secs: u64,
+    // Backfill window when an account has no sync state yet
+    pub fallback_days: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestSettings {
+    // Same capture (url + status + body content) within this window is dropped
+    pub dedup_window_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbSettings {
+    // None = app data dir; P2P_DB_PATH still wins. Cần restart
+    pub path: Option<PathBuf>,
+}
+
+impl Default for WsSettings {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:8123".into(),
+            buffer_size: 200,
+            // Content script on Binance pages. The popup's own chrome-extension://<id> is added by hand:
+            // a chrome-extension://* wildcard would admit every installed extension
+            allowed_origins: vec!["https://p2p.binance.com".into(), "https://www.binance.com".into()],
+        }
+    }
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self { Self { interval_secs: 300, fallback_days: 7 } }
+}
+
+impl Default for IngestSettings {
+    fn default() -> Self { Self { dedup_window_ms: 1500 } }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<()> {
+        let addr: SocketAddr = self.ws.addr.parse()
+            .with_context(|| format!("ws.addr '{}' is not an ip:port address", self.ws.addr))?;
+        // The bridge carries counterparty bank details: never expose it off the machine
+        if !addr.ip().is_loopback() { bail!("ws.addr must be a loopback address, got {}", self.ws.addr); }
+        if !(10..=10_000).contains(&self.ws.buffer_size) { bail!("ws.buffer_size must be 10..=10000"); }
+        if self.ws.allowed_origins.is_empty() { bail!("ws.allowed_origins must not be empty (the extension could not connect)"); }
+        if let Some(o) = self.ws.allowed_origins.iter().find(|o| !o.contains("://") || o.as_str() == "*") {
+            bail!("ws.allowed_origins entry '{o}' must be an origin like https://p2p.binance.com or chrome-extension://<extension id>");
+        }
+        if self.sync.interval_secs != 0 && self.sync.interval_secs < 30 { bail!("sync.interval_secs must be 0 (off) or >= 30"); }
+        if !(1..=365).contains(&self.sync.fallback_days) { bail!("sync.fallback_days must be 1..=365"); }
+        if self.ingest.dedup_window_ms > 60_000 { bail!("ingest.dedup_window_ms must be <= 60000"); }
+        if self.retention.completed_days < 0 || self.retention.cancelled_days < 0 { bail!("retention days must be >= 0"); }
+        if self.db.path.as_ref().is_some_and(|p| p.as_os_str().is_empty()) { bail!("db.path must not be empty"); }
+        Ok(())
+    }
+
+    /// Keys whose new value only takes effect after a restart
+    pub fn restart_required(&self, new: &Settings) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if self.ws.addr != new.ws.addr { keys.push("ws.addr"); }
+        if self.db.path != new.db.path { keys.push("db.path"); }
+        keys
+    }
+}
+
+/// Settings file + current values. `update` validates, saves, then swaps.
+pub struct SettingsStore {
+    path: PathBuf,
+    current: RwLock<Settings>,
+}
+
+impl SettingsStore {
+    /// Read `path`, writing the defaults there on first run so operators have a file to edit.
+    pub fn load(path: &Path) -> Result<Self> {
+        let settings = if path.exists() {
+            read(path)?
+        } else {
+            let defaults = Settings::default();
+            write(path, &defaults)?;
+            println!("[SETTINGS] Created {}", path.display());
+            defaults
+        };
+        Ok(Self { path: path.to_path_buf(), current: RwLock::new(settings) })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    pub fn get(&self) -> Settings { self.current.read().unwrap().clone() }
+
+    /// Validate and persist `new`; returns the previous settings
+    pub fn update(&self, new: Settings) -> Result<Settings> {
+        new.validate()?;
+        write(&self.path, &new)?;
+        Ok(std::mem::replace(&mut *self.current.write().unwrap(), new))
+    }
+
+    /// Re-read the file after a manual edit; returns (old, new)
+    pub fn reload(&self) -> Result<(Settings, Settings)> {
+        let new = read(&self.path)?;
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), new.clone());
+        Ok((old, new))
+    }
+}
+
+fn read(path: &Path) -> Result<Settings> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read settings {}", path.display()))?;
+    let settings: Settings = toml::from_str(&text)
+        .with_context(|| format!("Invalid settings file {}", path.display()))?;
+    settings.validate().with_context(|| format!("Invalid settings file {}", path.display()))?;
+    Ok(settings)
+}
+
+/// Write to a temp file then rename so a crash never leaves a half-written file
+fn write(path: &Path, settings: &Settings) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    let text = toml::to_string_pretty(settings)?;
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, text).with_context(|| format!("Cannot write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Cannot write {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use sqlx::SqlitePool;
+
+// 32 byte ngẫu nhiên -> 64 ký tự hex
+const TOKEN_BYTES: usize = 32;
+
+/// Secret shared with the paired extension (`ws_pairing`). The extension sends it in
+/// `HELLO`; anything else that can reach the port cannot inject captures.
+pub struct PairingRepo { pool: SqlitePool }
+
+impl PairingRepo {
+    pub fn new(pool: SqlitePool) -> Self { Self { pool } }
+
+    /// Current token; generated on first run
+    pub async fn token(&self) -> Result<String> {
+        let existing: Option<String> = sqlx::query_scalar("SELECT token FROM ws_pairing WHERE id = 1")
+            .fetch_optional(&self.pool).await?;
+        match existing {
+            Some(token) => Ok(token),
+            None => self.rotate().await
+        }
+    }
+
+    /// Replace the token; extensions paired with the old one must be paired again
+    pub async fn rotate(&self) -> Result<String> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+        sqlx::query("INSERT INTO ws_pairing (id, token, created_at) VALUES (1, ?1, ?2) ON CONFLICT(id) DO UPDATE SET token=excluded.token, created_at=excluded.created_at")
+            .bind(&token)
+            .bind(chrono::Utc::now().timestamp_millis())
+            .execute(&self.pool).await?;
+        Ok(token)
+    }
+}
+
+/// Constant-time so the token cannot be guessed byte by byte from response timing
+pub fn token_matches(expected: &str, presented: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), presented.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// `Origin` of the WS handshake against `ws.allowed_origins` (exact, or prefix for entries
+/// ending in `*`). Browsers always send it and pages cannot fake it; no header = rejected.
+pub fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    let Some(origin) = origin else { return false };
+    allowed.iter().any(|a| match a.strip_suffix('*') {
+        Some(prefix) => origin.starts_with(prefix),
+        None => origin.eq_ignore_ascii_case(a)
+    })
+}
@@ -0,0 +1,387 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, mpsc, oneshot, watch};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::accept_hdr_async;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use tungstenite::Message;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::protocol::frame::coding::CloseCode;
+use anyhow::{Result, bail};
+
+use crate::orders::ingest::{Ingestor, IngestOutcome};
+use crate::orders::store::OrderStore;
+
+pub mod auth;
+pub mod protocol;
+pub mod session;
+use protocol::{ClientFrame, ErrorCode, ExtensionCommand, ServerFrame};
+use session::{BridgeStatus, SessionChange, SessionEvent, SessionInfo, SessionListener, SessionRegistry};
+
+// Ping mỗi 20s; im lặng quá 60s (kể cả pong) = client chết, đóng kết nối
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+const DEAD_AFTER: Duration = Duration::from_secs(60);
+// Kết nối chưa HELLO sau 5s thì đóng (ping không giữ được nó)
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+// Chờ COMMAND_RESULT tối đa
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+// Seq đã ack nhớ lại để bản gửi lại sau reconnect không bị ingest hai lần
+const ACKED_PER_CLIENT: usize = 1000;
+const MAX_TRACKED_CLIENTS: usize = 50;
+
+// Trạng thái chia sẻ: buffer tối đa `buffer_size` message WebSocket (string) + pipeline ingest vào OrderStore
+#[derive(Clone)]
+pub struct WsState {
+    pub buffer: Arc<Mutex<Vec<String>>>,
+    buffer_size: Arc<AtomicUsize>,
+    pub ingestor: Arc<Ingestor>,
+    // Connected extension sessions (get_bridge_status, COMMAND targets)
+    sessions: Arc<SessionRegistry>,
+    // client_id -> seqs already acked
+    acked: Arc<Mutex<HashMap<String, AckedSeqs>>>,
+    // COMMAND id -> waiting send_command
+    pending: Arc<Mutex<HashMap<u64, oneshot::Sender<CommandReply>>>>,
+    next_conn_id: Arc<AtomicU64>,
+    next_command_id: Arc<AtomicU64>,
+    // Pairing token expected in HELLO; changing it drops paired connections
+    token: Arc<watch::Sender<String>>,
+    // ws.allowed_origins
+    allowed_origins: Arc<RwLock<Vec<String>>>
+}
+
+/// One extension's answer to a command
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandReply {
+    pub connection: u64,
+    pub ok: bool,
+    pub result: Value,
+    pub error: Option<String>
+}
+
+/// Per connection
+struct Conn {
+    id: u64,
+    // Protocol version agreed in HELLO; None until then
+    negotiated: Option<u32>,
+    client_id: Option<String>
+}
+
+#[derive(Default)]
+struct AckedSeqs {
+    order: VecDeque<u64>,
+    set: HashSet<u64>,
+    touched: Option<Instant>
+}
+
+impl AckedSeqs {
+    fn insert(&mut self, seq: u64) {
+        self.touched = Some(Instant::now());
+        if !self.set.insert(seq) { return; }
+        self.order.push_back(seq);
+        if self.order.len() > ACKED_PER_CLIENT {
+            if let Some(old) = self.order.pop_front() { self.set.remove(&old); }
+        }
+    }
+}
+
+impl WsState {
+    pub fn new(store: Arc<OrderStore>, dedup_window_ms: u64, buffer_size: usize, token: String, allowed_origins: Vec<String>) -> Self {
+        Self {
+            buffer: Arc::new(Mutex::new(Vec::new())),
+            buffer_size: Arc::new(AtomicUsize::new(buffer_size)),
+            ingestor: Arc::new(Ingestor::new(store, dedup_window_ms)),
+            sessions: Arc::new(SessionRegistry::default()),
+            acked: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            next_conn_id: Arc::new(AtomicU64::new(1)),
+            next_command_id: Arc::new(AtomicU64::new(1)),
+            token: Arc::new(watch::Sender::new(token)),
+            allowed_origins: Arc::new(RwLock::new(allowed_origins))
+        }
+    }
+
+    pub fn set_buffer_size(&self, size: usize) {
+        self.buffer_size.store(size, Ordering::Relaxed);
+    }
+
+    /// New pairing token; connections paired with the old one are closed
+    pub fn set_token(&self, token: String) {
+        self.token.send_replace(token);
+    }
+
+    pub fn set_allowed_origins(&self, origins: Vec<String>) {
+        *self.allowed_origins.write().unwrap() = origins;
+    }
+
+    pub async fn status(&self) -> BridgeStatus {
+        self.sessions.status().await
+    }
+
+    /// Send `command` to every connected v2 extension and collect the replies.
+    /// Connections that do not answer within COMMAND_TIMEOUT get `ok: false`.
+    pub async fn send_command(&self, command: ExtensionCommand) -> Result<Vec<CommandReply>> {
+        let peers = self.sessions.command_targets().await;
+        if peers.is_empty() { bail!("Extension chưa kết nối (cần protocol v2)"); }
+        let mut waiting = Vec::new();
+        for (connection, tx) in peers {
+            let id = self.next_command_id.fetch_add(1, Ordering::Relaxed);
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.pending.lock().await.insert(id, reply_tx);
+            if tx.send(ServerFrame::Command { id, command: command.clone() }).is_err() {
+                self.pending.lock().await.remove(&id);
+                continue;
+            }
+            waiting.push((connection, id, reply_rx));
+        }
+        let deadline = tokio::time::Instant::now() + COMMAND_TIMEOUT;
+        let mut replies = Vec::new();
+        for (connection, id, rx) in waiting {
+            let reply = match tokio::time::timeout_at(deadline, rx).await {
+                Ok(Ok(reply)) => CommandReply { connection, ..reply },
+                _ => {
+                    self.pending.lock().await.remove(&id);
+                    CommandReply { connection, ok: false, result: Value::Null, error: Some("No reply (timeout or disconnected)".into()) }
+                }
+            };
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+}
+
+/// Accept extension connections until the listener fails. `on_session` sees every
+/// connect / pair / disconnect.
+pub async fn start_ws_server(addr: &str, shared: WsState, on_session: SessionListener) -> Result<()> {
+    println!("[WS] Binding {addr}");
+    let listener = TcpListener::bind(addr).await?;
+    shared.sessions.set_listening(true);
+    let result = loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => break Err(e.into())
+        };
+        tauri::async_runtime::spawn(shared.clone().serve(stream, peer_addr, on_session.clone()));
+    };
+    shared.sessions.set_listening(false);
+    result
+}
+
+impl WsState {
+    /// One connection: Origin check, then frames until close / error / silence
+    async fn serve(self, stream: TcpStream, peer_addr: SocketAddr, on_session: SessionListener) {
+        let allowed = self.allowed_origins.read().unwrap().clone();
+        let mut origin: Option<String> = None;
+        // Signature fixed by tungstenite's handshake Callback
+        #[allow(clippy::result_large_err)]
+        let check_origin = |req: &Request, resp: Response| {
+            origin = req.headers().get("origin").and_then(|o| o.to_str().ok()).map(str::to_string);
+            if auth::origin_allowed(origin.as_deref(), &allowed) { return Ok(resp); }
+            eprintln!("[WS] Rejected connection from {peer_addr}, origin {origin:?}");
+            let mut denied = ErrorResponse::new(Some("Origin not allowed".into()));
+            *denied.status_mut() = StatusCode::FORBIDDEN;
+            Err(denied)
+        };
+        let ws_stream = match accept_hdr_async(stream, check_origin).await {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("[WS] Handshake error: {e}");
+                return;
+            }
+        };
+        let (tx, mut outbound) = mpsc::unbounded_channel();
+        let mut conn = Conn { id: self.next_conn_id.fetch_add(1, Ordering::Relaxed), negotiated: None, client_id: None };
+        let info = SessionInfo {
+            id: conn.id, peer_addr: peer_addr.to_string(), origin,
+            extension_version: None, protocol_version: None, client_id: None, page_url: None,
+            connected_at: chrono::Utc::now().timestamp_millis(),
+            last_message_at: None, last_capture_at: None,
+            messages: 0, captures: 0, errors: 0, last_error: None, disconnect_reason: None
+        };
+        self.sessions.open(info.clone(), tx).await;
+        on_session(&SessionEvent { change: SessionChange::Connected, session: info });
+        println!("[WS] Client #{} connected from {peer_addr}", conn.id);
+
+        let (mut write, mut read) = ws_stream.split();
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        let mut last_seen = Instant::now();
+        let mut token = self.token.subscribe();
+        let hello_deadline = tokio::time::sleep(HELLO_TIMEOUT);
+        tokio::pin!(hello_deadline);
+        let reason: String = loop {
+            let reply = tokio::select! {
+                msg = read.next() => {
+                    let Some(msg) = msg else { break "closed".into() };
+                    last_seen = Instant::now();
+                    match msg {
+                        Ok(Message::Text(txt)) => {
+                            let now = chrono::Utc::now().timestamp_millis();
+                            self.sessions.update(conn.id, |s| { s.messages += 1; s.last_message_at = Some(now); }).await;
+                            let was_paired = conn.negotiated.is_some();
+                            // Raw buffer (get_raw_messages) only sees paired traffic, never a HELLO and its token
+                            let (reply, keep_raw) = match protocol::parse_client_frame(&txt) {
+                                Ok(frame) => {
+                                    let keep_raw = was_paired && !matches!(frame, ClientFrame::Hello { .. });
+                                    (self.handle_frame(frame, &mut conn, &mut token).await, keep_raw)
+                                }
+                                Err(err) => (Some(err), was_paired)
+                            };
+                            if keep_raw { self.remember(&txt).await; }
+                            if !was_paired && conn.negotiated.is_some() {
+                                if let Some(session) = self.sessions.update(conn.id, |_| {}).await {
+                                    on_session(&SessionEvent { change: SessionChange::Paired, session });
+                                }
+                            }
+                            reply
+                        }
+                        // Pong / binary; tungstenite trả lời ping tự động
+                        Ok(_) => None,
+                        Err(e) => {
+                            eprintln!("[WS] Read error: {e}");
+                            break format!("read error: {e}");
+                        }
+                    }
+                }
+                Some(frame) = outbound.recv() => Some(frame),
+                _ = ping.tick() => {
+                    if last_seen.elapsed() >= DEAD_AFTER {
+                        println!("[WS] Client #{} silent for {}s, dropping", conn.id, last_seen.elapsed().as_secs());
+                        break format!("no traffic for {}s", last_seen.elapsed().as_secs());
+                    }
+                    if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                        eprintln!("[WS] Ping error: {e}");
+                        break format!("ping failed: {e}");
+                    }
+                    None
+                }
+                _ = &mut hello_deadline, if conn.negotiated.is_none() => {
+                    Some(ServerFrame::error(ErrorCode::HelloRequired, format!("No HELLO within {}s", HELLO_TIMEOUT.as_secs())))
+                }
+                Ok(()) = token.changed(), if conn.negotiated.is_some() => {
+                    Some(ServerFrame::error(ErrorCode::Unauthorized, "Pairing token changed; pair the extension again"))
+                }
+            };
+            let Some(reply) = reply else { continue };
+            let fatal = match &reply { ServerFrame::Error { code, .. } if code.is_fatal() => Some(*code), _ => None };
+            if let ServerFrame::Error { code, message, .. } = &reply {
+                eprintln!("[WS] Rejected frame ({code:?}): {message}");
+                let last_error = format!("{code:?}: {message}");
+                self.sessions.update(conn.id, |s| { s.errors += 1; s.last_error = Some(last_error); }).await;
+            }
+            if let Err(e) = write.send(Message::Text(reply.to_text())).await {
+                eprintln!("[WS] Send error: {e}");
+                break format!("send failed: {e}");
+            }
+            if let Some(code) = fatal {
+                let _ = write.send(Message::Close(Some(CloseFrame { code: CloseCode::Policy, reason: "protocol error".into() }))).await;
+                break format!("closed by app ({code:?})");
+            }
+        };
+        if let Some(mut session) = self.sessions.close(conn.id).await {
+            session.disconnect_reason = Some(reason.clone());
+            on_session(&SessionEvent { change: SessionChange::Disconnected, session });
+        }
+        println!("[WS] Client #{} disconnected ({reason})", conn.id);
+    }
+}
+
+impl WsState {
+    /// Raw frame -> buffer for get_raw_messages
+    async fn remember(&self, txt: &str) {
+        let mut g = self.buffer.lock().await;
+        g.push(txt.to_string());
+        // Vượt giới hạn: bỏ nửa cũ nhất
+        let max = self.buffer_size.load(Ordering::Relaxed);
+        if g.len() > max { let excess = g.len() - max / 2; g.drain(0..excess); }
+        println!("[WS] Received (total stored: {})", g.len());
+    }
+
+    /// Process one typed frame; returns the frame to send back, if any
+    /// `token` is the connection's view of the pairing token: HELLO marks the value it
+    /// checked as seen, so only a rotation after that point drops the connection.
+    async fn handle_frame(&self, frame: ClientFrame, conn: &mut Conn, token_rx: &mut watch::Receiver<String>) -> Option<ServerFrame> {
+        match frame {
+            ClientFrame::Hello { protocol_versions, extension_version, client_id, token } => {
+                let Some(v) = protocol::negotiate(&protocol_versions) else {
+                    return Some(ServerFrame::error(ErrorCode::UnsupportedVersion, format!(
+                        "Extension {extension_version} offers protocol {protocol_versions:?}, app supports {}..={}; update the {}",
+                        protocol::MIN_PROTOCOL_VERSION, protocol::PROTOCOL_VERSION,
+                        if protocol_versions.iter().any(|v| *v > protocol::PROTOCOL_VERSION) { "app" } else { "extension" })));
+                };
+                let paired = token.is_some_and(|t| auth::token_matches(&token_rx.borrow_and_update(), &t));
+                if !paired {
+                    return Some(ServerFrame::error(ErrorCode::Unauthorized,
+                        "Missing or wrong pairing token; copy it from the app (Settings > Extension pairing) into the extension popup"));
+                }
+                conn.negotiated = Some(v);
+                conn.client_id = client_id.clone();
+                self.sessions.update(conn.id, |s| {
+                    s.extension_version = Some(extension_version.clone());
+                    s.protocol_version = Some(v);
+                    s.client_id = client_id;
+                }).await;
+                println!("[WS] Client #{}: extension {extension_version} speaks protocol v{v}", conn.id);
+                Some(ServerFrame::Welcome { protocol_version: v, app_version: env!("CARGO_PKG_VERSION").into() })
+            }
+            _ if conn.negotiated.is_none() => Some(ServerFrame::error(ErrorCode::HelloRequired,
+                "Send HELLO first; an extension without HELLO is too old for this app")),
+            ClientFrame::NetCapture { seq, payload, page_url, .. } => {
+                let now = chrono::Utc::now().timestamp_millis();
+                let captured = |s: &mut SessionInfo| {
+                    s.captures += 1;
+                    s.last_capture_at = Some(now);
+                    if page_url.is_some() { s.page_url = page_url.clone(); }
+                };
+                // Resent after a reconnect but already stored: just ack again
+                if let (Some(seq), Some(client)) = (seq, &conn.client_id) {
+                    if self.acked.lock().await.get(client).is_some_and(|a| a.set.contains(&seq)) {
+                        self.sessions.update(conn.id, captured).await;
+                        return Some(ServerFrame::Ack { seq });
+                    }
+                }
+                match self.ingestor.ingest_capture(&payload.url, payload.status, &payload.data, now).await {
+                    Ok(IngestOutcome::List(n)) => println!("[INGEST] Order list -> {n} orders"),
+                    Ok(IngestOutcome::Detail(no)) => println!("[INGEST] Order detail {no}"),
+                    Ok(IngestOutcome::Nickname(nick)) => println!("[INGEST] User info ({nick})"),
+                    Ok(IngestOutcome::Duplicate) => println!("[INGEST] Duplicate capture skipped"),
+                    Ok(IngestOutcome::Ignored) => {}
+                    Err(e) => {
+                        eprintln!("[INGEST] {e}");
+                        // Not acked: the extension keeps it and resends on the next connection
+                        return seq.map(|seq| ServerFrame::Error {
+                            code: ErrorCode::IngestFailed, message: e.to_string(), supported: protocol::SUPPORTED, seq: Some(seq) });
+                    }
+                }
+                self.sessions.update(conn.id, captured).await;
+                let seq = seq?;
+                if let Some(client) = &conn.client_id { self.mark_acked(client, seq).await; }
+                Some(ServerFrame::Ack { seq })
+            }
+            ClientFrame::CommandResult { id, ok, result, error } => {
+                match self.pending.lock().await.remove(&id) {
+                    Some(waiter) => { let _ = waiter.send(CommandReply { connection: conn.id, ok, result, error }); }
+                    None => println!("[WS] Late or unknown COMMAND_RESULT {id} from client #{}", conn.id),
+                }
+                None
+            }
+            ClientFrame::Test { .. } => None,
+        }
+    }
+
+    async fn mark_acked(&self, client: &str, seq: u64) {
+        let mut acked = self.acked.lock().await;
+        if !acked.contains_key(client) && acked.len() >= MAX_TRACKED_CLIENTS {
+            // Quên client lâu không hoạt động nhất (tab đã đóng)
+            if let Some(stale) = acked.iter().min_by_key(|(_, a)| a.touched).map(|(k, _)| k.clone()) {
+                acked.remove(&stale);
+            }
+        }
+        acked.entry(client.to_string()).or_default().insert(seq);
+    }
+}
@@ -0,0 +1,209 @@
+//! Extension <-> app bridge protocol. Every frame is a JSON object tagged by `kind`.
+//!
+//! A connection starts with the extension's `HELLO` listing the protocol versions it
+//! speaks; the app answers `WELCOME` with the highest version both sides support, or an
+//! `ERROR` (`UNSUPPORTED_VERSION`) and closes. `HELLO` must carry the pairing token shown
+//! in the app, otherwise `ERROR` (`UNAUTHORIZED`) and close. Frames sent before `HELLO` are rejected.
+//!
+//! v2 adds delivery acknowledgement (`NET_CAPTURE.seq` -> `ACK`) and app -> extension
+//! `COMMAND`s answered with `COMMAND_RESULT`. v1 clients get neither.
+//!
+//! `p2p-extension/schema/*.schema.json` is generated from these types (`cargo run --bin ws_schema`).
+
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Newest protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version still accepted
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Extension -> app
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ClientFrame {
+    /// First frame on every connection
+    Hello {
+        /// Versions the extension can speak
+        protocol_versions: Vec<u32>,
+        extension_version: String,
+        /// Stable for the life of the content script; resent captures are matched by (client_id, seq)
+        #[serde(default)]
+        client_id: Option<String>,
+        /// Pairing token from the app's settings screen
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// One captured Binance API response
+    NetCapture {
+        /// v2: per-client increasing id, acknowledged with `ACK`. Keep the frame until then.
+        #[serde(default)]
+        seq: Option<u64>,
+        payload: Capture,
+        extension_version: String,
+        #[serde(default)]
+        page_url: Option<String>,
+        /// Extension clock, ms
+        #[serde(default)]
+        ts_send: Option<i64>,
+    },
+    /// Connection test from the extension popup
+    Test {
+        #[serde(default)]
+        payload: Value,
+    },
+    /// v2: answer to a `COMMAND` with the same `id`
+    CommandResult {
+        id: u64,
+        ok: bool,
+        #[serde(default)]
+        result: Value,
+        #[serde(default)]
+        error: Option<String>,
+    },
+}
+
+/// Kinds the app understands (an unknown `kind` gets `UNKNOWN_KIND`, not `INVALID_FRAME`)
+pub const CLIENT_KINDS: [&str; 4] = ["HELLO", "NET_CAPTURE", "TEST", "COMMAND_RESULT"];
+
+/// A fetch / XHR response captured by `injected.js`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Capture {
+    #[serde(rename = "type")]
+    pub transport: Transport,
+    pub url: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    /// HTTP status
+    pub status: i64,
+    /// Request duration, ms
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// Parsed JSON response body
+    pub data: Value,
+    #[serde(default, rename = "userAgent")]
+    pub user_agent: Option<String>,
+    /// ISO-8601 capture time
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    /// injected.js version
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Fetch,
+    Xhr,
+}
+
+/// App -> extension
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ServerFrame {
+    /// Reply to `HELLO`: the version used for the rest of the connection
+    Welcome {
+        protocol_version: u32,
+        app_version: String,
+    },
+    /// The offending frame was dropped; fatal errors are followed by a close
+    Error {
+        code: ErrorCode,
+        message: String,
+        supported: VersionRange,
+        /// `NET_CAPTURE` this error is about (not acknowledged: resend it later)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// v2: `NET_CAPTURE` `seq` was processed (or already had been); drop it from the retry queue
+    Ack {
+        seq: u64,
+    },
+    /// v2: ask the extension to do something; answered with `COMMAND_RESULT`
+    Command {
+        id: u64,
+        command: ExtensionCommand,
+    },
+}
+
+/// What the app can ask the extension to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExtensionCommand {
+    /// Re-request the order detail from Binance in the page; it arrives as a normal `NET_CAPTURE`
+    RefetchOrderDetail { order_number: String },
+    /// Reply with version, queue sizes and current page
+    ReportStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// No version in common (fatal)
+    UnsupportedVersion,
+    /// A frame other than `HELLO` arrived before the handshake, or no `HELLO` within 5s (fatal)
+    HelloRequired,
+    /// Missing or wrong pairing token, or the token was rotated (fatal)
+    Unauthorized,
+    /// Not JSON, or a known `kind` with missing / mistyped fields
+    InvalidFrame,
+    /// `kind` this app version does not know
+    UnknownKind,
+    /// Capture could not be stored (not acknowledged, resend later)
+    IngestFailed,
+}
+
+impl ErrorCode {
+    /// Connection is closed after sending this error
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ErrorCode::UnsupportedVersion | ErrorCode::HelloRequired | ErrorCode::Unauthorized)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+pub const SUPPORTED: VersionRange = VersionRange { min: MIN_PROTOCOL_VERSION, max: PROTOCOL_VERSION };
+
+/// Highest version both sides speak
+pub fn negotiate(offered: &[u32]) -> Option<u32> {
+    offered.iter().copied().filter(|v| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(v)).max()
+}
+
+impl ServerFrame {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerFrame::Error { code, message: message.into(), supported: SUPPORTED, seq: None }
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("ServerFrame serializes")
+    }
+}
+
+/// Parse one text frame. Err is the ERROR frame to send back.
+pub fn parse_client_frame(txt: &str) -> Result<ClientFrame, ServerFrame> {
+    let root: Value = serde_json::from_str(txt)
+        .map_err(|e| ServerFrame::error(ErrorCode::InvalidFrame, format!("Invalid JSON: {e}")))?;
+    let Some(kind) = root.get("kind").and_then(|k| k.as_str()).map(str::to_string) else {
+        return Err(ServerFrame::error(ErrorCode::InvalidFrame, "Frame must be an object with a string `kind`"));
+    };
+    if !CLIENT_KINDS.contains(&kind.as_str()) {
+        return Err(ServerFrame::error(ErrorCode::UnknownKind, format!("Unknown kind '{kind}'")));
+    }
+    serde_json::from_value(root)
+        .map_err(|e| ServerFrame::error(ErrorCode::InvalidFrame, format!("Invalid {kind} frame: {e}")))
+}
+
+/// (file name, JSON Schema) for both directions
+pub fn schemas() -> Vec<(&'static str, String)> {
+    let pretty = |v| serde_json::to_string_pretty(&v).expect("schema serializes") + "\n";
+    vec![
+        ("client-frame.schema.json", pretty(schema_for!(ClientFrame))),
+        ("server-frame.schema.json", pretty(schema_for!(ServerFrame))),
+    ]
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use serde::Serialize;
+use tokio::sync::{Mutex, mpsc};
+
+use super::protocol::ServerFrame;
+
+/// One WebSocket connection that passed the Origin check
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer_addr: String,
+    pub origin: Option<String>,
+    // Known after HELLO
+    pub extension_version: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub client_id: Option<String>,
+    /// Binance page of the last capture
+    pub page_url: Option<String>,
+    /// Epoch ms
+    pub connected_at: i64,
+    pub last_message_at: Option<i64>,
+    pub last_capture_at: Option<i64>,
+    /// Text frames received
+    pub messages: u64,
+    /// NET_CAPTURE frames stored (or already stored)
+    pub captures: u64,
+    /// ERROR frames sent back
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// Why it ended; only set on the `disconnected` event
+    pub disconnect_reason: Option<String>,
+}
+
+impl SessionInfo {
+    /// Passed HELLO (version + pairing token)
+    pub fn paired(&self) -> bool { self.protocol_version.is_some() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionChange {
+    Connected,
+    /// HELLO accepted
+    Paired,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEvent {
+    pub change: SessionChange,
+    pub session: SessionInfo,
+}
+
+/// Called on every session change (the app forwards it to the webview)
+pub type SessionListener = Arc<dyn Fn(&SessionEvent) + Send + Sync>;
+
+/// Snapshot for `get_bridge_status`
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeStatus {
+    /// WS server is bound and accepting
+    pub listening: bool,
+    /// At least one paired extension is connected
+    pub live: bool,
+    /// Last capture stored from any session, including closed ones (epoch ms)
+    pub last_capture_at: Option<i64>,
+    pub sessions: Vec<SessionInfo>,
+}
+
+struct Session {
+    info: SessionInfo,
+    // Frames pushed from outside the read loop (COMMAND)
+    tx: mpsc::UnboundedSender<ServerFrame>,
+}
+
+/// Connected sessions by connection id
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<u64, Session>>,
+    listening: AtomicBool,
+    // 0 = chưa có capture nào
+    last_capture_at: AtomicI64,
+}
+
+impl SessionRegistry {
+    pub fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::Relaxed);
+    }
+
+    pub async fn open(&self, info: SessionInfo, tx: mpsc::UnboundedSender<ServerFrame>) {
+        self.sessions.lock().await.insert(info.id, Session { info, tx });
+    }
+
+    /// Change a session and return the updated copy (None if already closed)
+    pub async fn update(&self, id: u64, f: impl FnOnce(&mut SessionInfo)) -> Option<SessionInfo> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&id)?;
+        f(&mut session.info);
+        if let Some(ts) = session.info.last_capture_at {
+            self.last_capture_at.fetch_max(ts, Ordering::Relaxed);
+        }
+        Some(session.info.clone())
+    }
+
+    pub async fn close(&self, id: u64) -> Option<SessionInfo> {
+        self.sessions.lock().await.remove(&id).map(|s| s.info)
+    }
+
+    /// Paired sessions that understand COMMAND (protocol v2+)
+    pub async fn command_targets(&self) -> Vec<(u64, mpsc::UnboundedSender<ServerFrame>)> {
+        self.sessions.lock().await.values()
+            .filter(|s| s.info.protocol_version.is_some_and(|v| v >= 2))
+            .map(|s| (s.info.id, s.tx.clone()))
+            .collect()
+    }
+
+    pub async fn status(&self) -> BridgeStatus {
+        let mut sessions: Vec<SessionInfo> = self.sessions.lock().await.values().map(|s| s.info.clone()).collect();
+        sessions.sort_by_key(|s| s.id);
+        let last = self.last_capture_at.load(Ordering::Relaxed);
+        BridgeStatus {
+            listening: self.listening.load(Ordering::Relaxed),
+            live: sessions.iter().any(SessionInfo::paired),
+            last_capture_at: (last > 0).then_some(last),
+            sessions,
+        }
+    }
+}
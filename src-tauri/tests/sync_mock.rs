@@ -0,0 +1,94 @@
+//! SyncEngine against the mock_binance server on an ephemeral port.
+
+#[allow(dead_code)]
+#[path = "../src/bin/mock_binance.rs"]
+mod mock_binance;
+
+use std::sync::Mutex;
+use std::time::Instant;
+use mock_binance::MockConfig;
+use tauri_app_lib::{C2CApiClient, CryptoCtx, Db, OrderRepo, SyncEngine, SyncProgress, SyncStateRepo, MAX_WINDOW_MS};
+use tokio::net::TcpListener;
+
+const RECV_WINDOW_MS: u64 = 10_000;
+
+struct Harness {
+    client: C2CApiClient,
+    repo: OrderRepo,
+    state: SyncStateRepo
+}
+
+async fn start(name: &str, cfg: MockConfig) -> Harness {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (key, secret) = (cfg.api_key.clone(), cfg.api_secret.clone());
+    tokio::spawn(mock_binance::serve(listener, cfg));
+
+    let path = std::env::temp_dir().join(format!("p2p-sync-mock-{}-{name}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = Db::init(&path).await.unwrap();
+    Harness {
+        client: C2CApiClient::new(key, secret).with_base_url(&base).with_recv_window(RECV_WINDOW_MS),
+        repo: OrderRepo::new(db.pool().clone(), CryptoCtx::locked()),
+        state: SyncStateRepo::new(db.pool().clone())
+    }
+}
+
+/// Initial sync over `days`, returning the order count and every progress report
+async fn initial_sync(h: &Harness, days: i64) -> (usize, Vec<SyncProgress>) {
+    let seen = Mutex::new(Vec::new());
+    let record = |p: &SyncProgress| seen.lock().unwrap().push(p.clone());
+    let total = SyncEngine::new(&h.client, &h.repo, &h.state, 1)
+        .with_progress(&record)
+        .force_initial_sync(days).await
+        .unwrap();
+    (total, seen.into_inner().unwrap())
+}
+
+#[tokio::test]
+async fn paginates_until_short_page() {
+    let h = start("pages", MockConfig { orders: 250, days: 20, ..Default::default() }).await;
+    let (total, progress) = initial_sync(&h, 21).await;
+    assert_eq!(total, 250);
+    // 125 orders per trade type -> pages of 100 + 25, one window each
+    assert_eq!(progress.len(), 2);
+    for p in &progress {
+        assert_eq!((p.window_count, p.pages, p.orders), (1, 2, 125), "{}", p.trade_type);
+    }
+}
+
+#[tokio::test]
+async fn splits_ranges_longer_than_30_days() {
+    // mock rejects any request spanning more than 30 days
+    let h = start("windows", MockConfig { orders: 90, days: 45, ..Default::default() }).await;
+    let (total, progress) = initial_sync(&h, 46).await;
+    assert_eq!(total, 90);
+    assert_eq!(progress.len(), 4);
+    for p in &progress {
+        assert_eq!(p.window_count, 2);
+        assert!(p.window_end - p.window_start <= MAX_WINDOW_MS);
+    }
+    assert_eq!(progress.iter().map(|p| p.orders).sum::<usize>(), 90);
+}
+
+#[tokio::test]
+async fn retries_server_errors_and_429_with_retry_after() {
+    let h = start("retry", MockConfig { orders: 250, days: 20, fail_every: 5, rate_limit_every: 3, ..Default::default() }).await;
+    let started = Instant::now();
+    let (total, _) = initial_sync(&h, 21).await;
+    assert_eq!(total, 250);
+    // every 3rd request gets 429 + Retry-After: 1, every 5th a 500
+    assert!(started.elapsed().as_millis() >= 1000, "Retry-After not honoured: {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn corrects_clock_skew_of_signed_timestamp() {
+    let skew = 2 * 60 * 1000;
+    let h = start("skew", MockConfig { orders: 20, days: 5, clock_skew_ms: skew, ..Default::default() }).await;
+    assert_eq!(h.client.time_offset_ms(), 0);
+    // unsynced timestamps would be 2 min outside recvWindow; the client measures the offset first
+    let (total, _) = initial_sync(&h, 6).await;
+    assert_eq!(total, 20);
+    let offset = h.client.time_offset_ms();
+    assert!((offset - skew).abs() < 2000, "offset {offset} vs skew {skew}");
+}